@@ -0,0 +1,22 @@
+//! Read-only access to VDFS4 filesystem images.
+//!
+//! Open an image with [`Vdfs::open`], then use the catalog, extent and xattr
+//! trees or the unpacker to get at its content.
+
+pub mod vdfs;
+
+pub use vdfs::{
+    btree::check::BnodeFinding,
+    check::CheckFinding,
+    compressed::{AuthVerdict, ContentVerdict, VdfsCompressedFile},
+    data_source::{DataPointer, DataSource, DataSourceError, DataSourceSource},
+    encryption::read_encryption_key,
+    file::VdfsFile,
+    lookup::{CatalogEntry, CatalogRecord},
+    metadata::Metadata,
+    read_dir::DirEntry,
+    signature::{read_public_key, SignatureVerdict},
+    snapshot::BaseTableInfo,
+    unpack::UnpackOptions,
+    Vdfs, VdfsError,
+};
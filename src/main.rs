@@ -1,32 +1,191 @@
 use std::env;
 use std::fs::{self, File};
+use std::process;
 
-use vdfs::data_source::DataSourceSource;
+use vdfs_utils::{
+    read_encryption_key, read_public_key, DataSource, SignatureVerdict, UnpackOptions, Vdfs,
+};
 
-use crate::vdfs::{data_source, Vdfs};
+fn main() {
+    let mut options = UnpackOptions::default();
+    let mut paths = Vec::new();
+    let mut list_base_tables = false;
+    let mut check = false;
+    let mut base_table_index = None;
+    let mut public_key_path = None;
+    let mut encryption_key_path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--symlinks-as-files" => options.symlinks_as_files = true,
+            "--no-metadata" => options.skip_metadata = true,
+            "--no-xattrs" => options.skip_xattrs = true,
+            "--skip-privileged-xattrs" => options.skip_privileged_xattrs = true,
+            "--extract-orphans" => options.extract_orphans = true,
+            "--list-base-tables" => list_base_tables = true,
+            "--check" => check = true,
+            _ if arg.starts_with("--verify-signatures=") => {
+                public_key_path = arg.split_once('=').map(|(_, path)| String::from(path))
+            }
+            _ if arg.starts_with("--key=") => {
+                encryption_key_path = arg.split_once('=').map(|(_, path)| String::from(path))
+            }
+            _ if arg.starts_with("--base-table=") => {
+                let (_, index) = arg.split_once('=').unwrap();
+                base_table_index = Some(index.parse().expect("Invalid base table index"));
+            }
+            _ if arg.starts_with("--xattrs-dump=") => {
+                options.xattrs_dump_path = arg.split_once('=').map(|(_, path)| String::from(path))
+            }
+            _ if arg.starts_with("--uid-map=") => {
+                let (image_id, local_id) = parse_id_mapping(&arg);
+                options.uid_map.insert(image_id, local_id);
+            }
+            _ if arg.starts_with("--gid-map=") => {
+                let (image_id, local_id) = parse_id_mapping(&arg);
+                options.gid_map.insert(image_id, local_id);
+            }
+            _ if arg.starts_with("--special-files-manifest=") => {
+                options.special_files_manifest_path =
+                    arg.split_once('=').map(|(_, path)| String::from(path))
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option: {}", arg),
+            _ => paths.push(arg),
+        }
+    }
+    let mut paths = paths.into_iter();
+    let input_path = paths.next().expect("Input file not specified");
 
-mod vdfs;
+    let vdfs_file = File::open(&input_path).expect("Cannot open file");
+    let data_source = DataSource::from_source(vdfs_file);
 
-impl DataSourceSource for File {}
+    if list_base_tables {
+        let mut vdfs = Vdfs::new(&data_source).expect("Cannot initialize Vdfs");
+        warn_about_super_block_copy(&vdfs);
+        vdfs.init_current_base_table().ok();
+        print_base_tables(&vdfs);
+        return;
+    }
 
-fn main() {
-    let input_path = env::args().nth(1).expect("Input file not specified");
-    let output_path = env::args().nth(2).expect("Output file not specified");
+    let mut vdfs = match base_table_index {
+        Some(index) => Vdfs::open_at_base_table(&data_source, index),
+        None => Vdfs::open(&data_source),
+    }
+    .expect("Cannot initialize Vdfs");
+    warn_about_super_block_copy(&vdfs);
+    if let Some(encryption_key_path) = encryption_key_path {
+        vdfs.set_encryption_key(
+            read_encryption_key(&encryption_key_path).expect("Cannot read encryption key"),
+        );
+    }
+
+    if check {
+        let is_consistent = check_filesystem(&vdfs);
+        process::exit(if is_consistent { 0 } else { 1 });
+    }
+
+    if let Some(public_key_path) = public_key_path {
+        let all_valid = verify_signatures(&vdfs, &public_key_path);
+        process::exit(if all_valid { 0 } else { 1 });
+    }
+
+    let output_path = paths.next().expect("Output file not specified");
 
     println!(
         "Unpacking vdfs filesystem from {} to folder: {}",
         &input_path, &output_path
     );
 
-    let vdfs_file = File::open(&input_path).expect("Cannot open file");
-    let data_source = data_source::DataSource::from_source(vdfs_file);
-
     fs::remove_dir_all(&output_path).ok();
 
-    let mut vdfs = Vdfs::new(&data_source).expect("Cannot initialize Vdfs");
+    vdfs.unpack_with_options(&output_path, &options).unwrap();
+}
+
+fn warn_about_super_block_copy(vdfs: &Vdfs<File>) {
+    if let Some(copy_index) = vdfs.get_used_super_block_copy() {
+        println!(
+            "Superblock is damaged, using its redundant copy {}",
+            copy_index + 1
+        );
+    }
+}
+
+fn print_base_tables(vdfs: &Vdfs<File>) {
+    for base_table in vdfs.list_base_tables().expect("Cannot read base tables") {
+        println!(
+            "Base table {} at {}: mount_count {}, sync_count {}, {}, {} extended tables{}",
+            base_table.index,
+            base_table.position,
+            base_table.mount_count,
+            base_table.sync_count,
+            if !base_table.has_valid_header {
+                "invalid header"
+            } else if !base_table.has_valid_crc32 {
+                "CRC mismatch"
+            } else {
+                "CRC ok"
+            },
+            base_table.extended_tables_count,
+            if base_table.is_current {
+                ", current"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Returns false if the checker found any problem.
+fn check_filesystem(vdfs: &Vdfs<File>) -> bool {
+    let findings = vdfs.check().expect("Cannot check filesystem");
+    for finding in &findings {
+        println!("{:?}", finding);
+    }
+    println!("Problems found: {}", findings.len());
+    findings.is_empty()
+}
+
+/// Returns false if any signature is invalid or a signed file cannot be verified.
+fn verify_signatures(vdfs: &Vdfs<File>, public_key_path: &str) -> bool {
+    let public_key = read_public_key(public_key_path).expect("Cannot read public key");
+
+    let super_block_verdict = vdfs
+        .verify_super_block_signature(&public_key)
+        .expect("Cannot verify superblock");
+    println!("Superblock signature: {:?}", super_block_verdict);
+    let mut invalid_count = (super_block_verdict == SignatureVerdict::Invalid) as usize;
+
+    let file_verdicts = vdfs
+        .verify_file_signatures(&public_key)
+        .expect("Cannot verify files");
+    for (path, verdict) in &file_verdicts {
+        match verdict {
+            Ok(verdict) => {
+                println!("{:?}: {}", verdict, path);
+                if *verdict != SignatureVerdict::Ok {
+                    invalid_count += 1;
+                }
+            }
+            Err(e) => {
+                println!("Cannot verify {}: {:?}", path, e);
+                invalid_count += 1;
+            }
+        }
+    }
+    println!(
+        "Signed files: {}, invalid or unverifiable: {}",
+        file_verdicts.len(),
+        invalid_count
+    );
+    invalid_count == 0
+}
 
-    vdfs.init_current_base_table()
-        .expect("Cannot initialize base table");
-    vdfs.init_btrees().expect("Cannot initialize Btrees");
-    vdfs.unpack(&output_path).unwrap();
+fn parse_id_mapping(arg: &str) -> (u32, u32) {
+    let (_, mapping) = arg.split_once('=').unwrap();
+    let (image_id, local_id) = mapping
+        .split_once(':')
+        .unwrap_or_else(|| panic!("Mapping must be <image id>:<local id>: {}", arg));
+    (
+        image_id.parse().expect("Invalid image id"),
+        local_id.parse().expect("Invalid local id"),
+    )
 }
@@ -9,19 +9,33 @@ use std::{
 use self::{
     btree::catalog::CatalogTree,
     btree::xattr::XattrTree,
-    btree::{extent::ExtentTree, BtreeError},
+    btree::{extent::ExtentTree, BtreeError, TranslationOverlay},
     data_source::*,
     layout::consts::*,
     layout::*,
 };
 
 pub mod btree;
+pub mod check;
+pub mod compressed;
 pub mod data_source;
+pub mod encryption;
+pub mod file;
 pub mod layout;
+pub mod lzo;
 pub mod vdfs_crc;
 
+pub mod lookup;
+pub mod metadata;
+pub mod orphan;
+pub mod read_dir;
+pub mod signature;
+pub mod snapshot;
 pub mod unpack;
 
+#[cfg(test)]
+mod test_image;
+
 #[derive(Debug)]
 pub enum VdfsError {
     BtreeError(BtreeError),
@@ -33,17 +47,50 @@ pub enum VdfsError {
     CompressedFileExtentWrongSignature,
     CannotDecompressFileWithoutCompression,
     CannotFindParentFolder,
+    BtreesAreNotInitialized(),
+    PathNotFound(String),
+    NotADirectory(String),
+    UnsupportedCatalogRecordType(u8),
+    HardLinkInodeNotFound(u64),
+    NotAFile(String),
+    CompressedFileChunkNotFound(u64),
+    NotASymbolicLink(String),
+    UnsupportedFileMode(u16),
+    OrphanInodeNotFound(u64),
+    OrphanListLoop(u64),
+    InvalidBaseTable(u32),
+    SuperBlockSignatureMismatch(),
+    UnsupportedLayoutVersion(String),
+    UnsupportedCompressedFileLayout(u16),
+    SuperBlockChecksumMismatch(u32, u32),
+    ExtendedSuperBlockChecksumMismatch(u32, u32),
+    UnsupportedGeometry(u8, u8),
+    PublicKeyError(String),
+    EncryptionKeyError(String),
+    EncryptionKeyMissing(),
 }
 
+pub type BaseTables = (
+    Option<DataPointer<Vdfs4BaseTable>>,
+    Option<DataPointer<Vdfs4BaseTable>>,
+);
+
+/// Extended tables that follow a base table, oldest first, with their records.
+pub type ExtendedTables = Vec<(DataPointer<Vdfs4ExtendedTable>, Vec<Vdfs4ExtendedRecord>)>;
+
 pub struct Vdfs<'a, S: DataSourceSource> {
     data_source: &'a DataSource<S>,
     block_size: u64,
 
     super_blocks: Vdfs4SuperBlocks,
+    used_super_block_copy: Option<usize>,
 
     bitmap_size_in_bytes: u64,
 
     current_base_table: Option<DataPointer<Vdfs4BaseTable>>,
+    current_extended_tables: ExtendedTables,
+
+    encryption_key: Option<[u8; VDFS4_AES_KEY_LENGTH]>,
 
     catalog_btree: Option<CatalogTree<'a, S>>,
     extent_btree: Option<ExtentTree<'a, S>>,
@@ -51,28 +98,107 @@ pub struct Vdfs<'a, S: DataSourceSource> {
 }
 
 impl<'a, S: DataSourceSource> Vdfs<'a, S> {
-    pub fn new(data_source: &'a DataSource<S>) -> Result<Vdfs<'a, S>, BtreeError> {
-        let block_size: u64 = consts::BLOCK_SIZE_DEFAULT;
-        let super_page_size = consts::SUPER_PAGE_SIZE_DEFAULT;
+    /// Reads the superblocks, selects the current base table and loads all B-trees.
+    pub fn open(data_source: &'a DataSource<S>) -> Result<Vdfs<'a, S>, VdfsError> {
+        let mut vdfs = Self::new(data_source)?;
+        vdfs.init_current_base_table()?;
+        vdfs.init_btrees()?;
+        Ok(vdfs)
+    }
 
+    pub fn new(data_source: &'a DataSource<S>) -> Result<Vdfs<'a, S>, VdfsError> {
         let super_blocks_offset = 0;
         let super_blocks: Vdfs4SuperBlocks = data_source.read_at(super_blocks_offset)?.data;
 
-        let bitmap_size_in_bytes =
-            super_page_size - size_of::<Vdfs4HeadBtreeNode>() as u64 - size_of::<u32>() as u64;
-
-        Ok(Self {
+        let mut vdfs = Self {
             data_source,
-            block_size,
+            // Set from the validated superblock by init_geometry
+            block_size: 0,
             super_blocks,
-            bitmap_size_in_bytes,
+            used_super_block_copy: None,
+            bitmap_size_in_bytes: 0,
 
             current_base_table: None,
+            current_extended_tables: Vec::new(),
+
+            encryption_key: None,
 
             catalog_btree: None,
             extent_btree: None,
             xattr_btree: None,
-        })
+        };
+        vdfs.init_super_block()?;
+        vdfs.init_geometry()?;
+        Ok(vdfs)
+    }
+
+    fn init_geometry(&mut self) -> Result<(), VdfsError> {
+        let super_block = &self.super_blocks.super_block;
+        let unsupported_geometry = VdfsError::UnsupportedGeometry(
+            super_block.log_block_size,
+            super_block.log_super_page_size,
+        );
+        if !super_block.has_supported_geometry() {
+            return Err(unsupported_geometry);
+        }
+        // The head node starts with its descriptor and ends with CRC32, the rest is the bitmap
+        self.bitmap_size_in_bytes = super_block
+            .get_super_page_size()
+            .checked_sub(size_of::<Vdfs4HeadBtreeNode>() as u64 + size_of::<u32>() as u64)
+            .ok_or(unsupported_geometry)?;
+        self.block_size = super_block.get_block_size();
+        Ok(())
+    }
+
+    /// Validates the superblocks, a damaged primary superblock is replaced by the first valid
+    /// redundant copy. The extended superblock has no copies.
+    fn init_super_block(&mut self) -> Result<(), VdfsError> {
+        if let Err(primary_error) = self.check_super_block(&self.super_blocks.super_block) {
+            let (copy_index, valid_copy) = self
+                .super_blocks
+                .get_super_block_copies()
+                .into_iter()
+                .enumerate()
+                .find(|(_, copy)| self.check_super_block(copy).is_ok())
+                .ok_or(primary_error)?;
+            self.super_blocks.super_block = *valid_copy;
+            self.used_super_block_copy = Some(copy_index);
+        }
+
+        let ext_super_block = &self.super_blocks.ext_super_block;
+        let crc32_calculated = self.calc_crc32(ext_super_block)?;
+        if ext_super_block.get_crc32() != crc32_calculated {
+            return Err(VdfsError::ExtendedSuperBlockChecksumMismatch(
+                ext_super_block.get_crc32(),
+                crc32_calculated,
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_super_block(&self, super_block: &Vdfs4SuperBlock) -> Result<(), VdfsError> {
+        if !super_block.check_signature(VDFS4_SB_SIGNATURE) {
+            return Err(VdfsError::SuperBlockSignatureMismatch());
+        }
+        // The kernel driver decodes both layouts with the single struct vdfs4_super_block
+        // from fs/vdfs4/vdfs4_layout.h and accepts either version string at mount time,
+        // so they share Vdfs4SuperBlock here as well
+        match super_block.get_layout_version() {
+            Some(VdfsLayoutVersion::V2006 | VdfsLayoutVersion::V2007) => {}
+            None => {
+                return Err(VdfsError::UnsupportedLayoutVersion(
+                    super_block.get_layout_version_string(),
+                ))
+            }
+        }
+        let crc32_calculated = self.calc_crc32(super_block)?;
+        if super_block.get_crc32() != crc32_calculated {
+            return Err(VdfsError::SuperBlockChecksumMismatch(
+                super_block.get_crc32(),
+                crc32_calculated,
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -81,13 +207,53 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
         &self.super_blocks
     }
 
+    /// Index of the redundant superblock copy used instead of the damaged primary superblock.
+    pub fn get_used_super_block_copy(&self) -> Option<usize> {
+        self.used_super_block_copy
+    }
+
+    pub fn get_block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Layout of the validated superblock.
+    pub fn get_layout_version(&self) -> Option<VdfsLayoutVersion> {
+        self.super_blocks.super_block.get_layout_version()
+    }
+
+    pub fn get_current_base_table(&self) -> Option<&DataPointer<Vdfs4BaseTable>> {
+        self.current_base_table.as_ref()
+    }
+
+    pub fn get_current_extended_tables(&self) -> &ExtendedTables {
+        &self.current_extended_tables
+    }
+
+    pub fn get_catalog_tree(&self) -> Result<&CatalogTree<'a, S>, VdfsError> {
+        self.catalog_btree
+            .as_ref()
+            .ok_or(VdfsError::BtreesAreNotInitialized())
+    }
+
+    pub fn get_extent_tree(&self) -> Result<&ExtentTree<'a, S>, VdfsError> {
+        self.extent_btree
+            .as_ref()
+            .ok_or(VdfsError::BtreesAreNotInitialized())
+    }
+
+    pub fn get_xattr_tree(&self) -> Result<&XattrTree<'a, S>, VdfsError> {
+        self.xattr_btree
+            .as_ref()
+            .ok_or(VdfsError::BtreesAreNotInitialized())
+    }
+
     pub fn read_btree_head(
         &self,
         extent_offset: u64,
     ) -> Result<DataPointer<Vdfs4HeadBtreeNode>, VdfsError> {
         self.data_source
             .read_at(self.blocks_to_bytes(extent_offset))
-            .map_err(|e| VdfsError::DataSourceError(e))
+            .map_err(VdfsError::DataSourceError)
     }
 
     pub fn read_bitmap_bit(&self, extent_offset: u64, bit_offset: u64) -> Result<bool, BtreeError> {
@@ -108,37 +274,55 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
     }
 
     fn get_base_table_offset(&self, index: u32) -> u64 {
-        assert!(index < 2);
+        assert!(index < VDFS4_SNAPSHOT_BASE_TABLES);
         let tables_extent_begin = self.super_blocks.ext_super_block.tables.begin;
-        let tables_extent_length = self.super_blocks.ext_super_block.tables.length;
-        let tables_length_in_bytes = self.blocks_to_bytes(tables_extent_length);
         let first_table_offset_in_bytes = self.blocks_to_bytes(tables_extent_begin);
-        first_table_offset_in_bytes + index as u64 * (tables_length_in_bytes / 2)
+        first_table_offset_in_bytes + index as u64 * self.get_base_table_max_size()
     }
 
-    pub fn init_current_base_table(&mut self) -> Result<(), VdfsError> {
-        let base_tables = self.read_base_tables()?;
-        let first = base_tables.0.ok_or(VdfsError::BaseTableIsMissing())?;
+    /// Tables extent is split in halves between the base tables and their extended tables.
+    fn get_base_table_max_size(&self) -> u64 {
+        let tables_extent_length = self.super_blocks.ext_super_block.tables.length;
+        self.blocks_to_bytes(tables_extent_length) / VDFS4_SNAPSHOT_BASE_TABLES as u64
+    }
 
-        if let Some(second) = base_tables.1 {
-            if first.data.descriptor.get_version() < second.data.descriptor.get_version() {
-                self.current_base_table = Some(second);
-                return Ok(());
+    pub fn init_current_base_table(&mut self) -> Result<(), VdfsError> {
+        // A damaged table is skipped, so the volume opens while any of them is valid
+        let current = match self.read_base_tables()? {
+            (Some(first), Some(second))
+                if first.data.descriptor.get_version() < second.data.descriptor.get_version() =>
+            {
+                second
             }
+            (Some(first), _) => first,
+            (None, Some(second)) => second,
+            (None, None) => return Err(VdfsError::BaseTableIsMissing()),
+        };
+        self.set_current_base_table(current)
+    }
+
+    /// Selects the base table with the given index, even if it is not the newest one.
+    /// B-trees have to be initialized again after that.
+    pub fn select_base_table(&mut self, index: u32) -> Result<(), VdfsError> {
+        if index >= VDFS4_SNAPSHOT_BASE_TABLES {
+            return Err(VdfsError::InvalidBaseTable(index));
         }
-        self.current_base_table = Some(first);
+        let base_table = self
+            .read_base_table(self.get_base_table_offset(index))?
+            .ok_or(VdfsError::InvalidBaseTable(index))?;
+        self.set_current_base_table(base_table)
+    }
+
+    fn set_current_base_table(
+        &mut self,
+        base_table: DataPointer<Vdfs4BaseTable>,
+    ) -> Result<(), VdfsError> {
+        self.current_extended_tables = self.read_extended_tables(&base_table)?;
+        self.current_base_table = Some(base_table);
         Ok(())
     }
 
-    pub fn read_base_tables(
-        &self,
-    ) -> Result<
-        (
-            Option<DataPointer<Vdfs4BaseTable>>,
-            Option<DataPointer<Vdfs4BaseTable>>,
-        ),
-        VdfsError,
-    > {
+    pub fn read_base_tables(&self) -> Result<BaseTables, VdfsError> {
         let first_table_offset_in_bytes = self.get_base_table_offset(0);
         let second_table_offset_in_bytes = self.get_base_table_offset(1);
 
@@ -153,13 +337,7 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
         offset_in_bytes: u64,
     ) -> Result<Option<DataPointer<Vdfs4BaseTable>>, VdfsError> {
         let base_table: DataPointer<Vdfs4BaseTable> = self.data_source.read_at(offset_in_bytes)?;
-        let size_without_crc32 = base_table.data.descriptor.checksum_offset;
-        if !base_table
-            .data
-            .descriptor
-            .check_signature(VDFS4_SNAPSHOT_BASE_TABLE)
-            || base_table.data.descriptor.checksum_offset > size_without_crc32
-        {
+        if !self.check_base_table_header(&base_table.data) {
             return Ok(Option::None);
         }
         if self
@@ -170,6 +348,14 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
         Ok(Option::None)
     }
 
+    fn check_base_table_header(&self, base_table: &Vdfs4BaseTable) -> bool {
+        base_table
+            .descriptor
+            .check_signature(VDFS4_SNAPSHOT_BASE_TABLE)
+            && base_table.descriptor.checksum_offset + CRC32_SIZE as u64
+                <= self.get_base_table_max_size()
+    }
+
     fn check_crc32_of_snapshot_descriptor(
         &self,
         offset_in_bytes: u64,
@@ -186,38 +372,74 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
         Ok(crc32_from_data == crc32_calculated)
     }
 
-    pub fn read_extended_tables(&self, base_table: &DataPointer<Vdfs4BaseTable>) {
-        let base_table_offset = base_table.position;
+    /// Extended tables are written one per sync after the base table, the chain ends at the
+    /// first table that is invalid or not newer than the previous one.
+    pub fn read_extended_tables(
+        &self,
+        base_table: &DataPointer<Vdfs4BaseTable>,
+    ) -> Result<ExtendedTables, VdfsError> {
         let base_table_size = base_table.data.descriptor.checksum_offset as usize + CRC32_SIZE;
-        let mut extended_table_offset =
-            base_table_offset + size_ceil_to_block(base_table_size, VDFS4_SNAPSHOT_EXT_SIZE) as u64;
-
-        for extended_table_index in 0..VDFS4_SNAPSHOT_EXT_TABLES {
-            let extended_table: Vdfs4ExtendedTable = self
-                .data_source
-                .read_at(extended_table_offset)
-                .unwrap()
-                .data;
-
-            println!(
-                "Base table at {}, Extended Table at {}: {:?}",
-                base_table_offset, extended_table_offset, extended_table
-            );
-
-            if !extended_table
-                .descriptor
-                .check_signature(VDFS4_SNAPSHOT_EXTENDED_TABLE)
-                || !(self
-                    .check_crc32_of_snapshot_descriptor(
-                        extended_table_offset,
-                        &extended_table.descriptor,
-                    )
-                    .unwrap())
+        let mut extended_table_offset = base_table.position
+            + size_ceil_to_block(base_table_size, VDFS4_SNAPSHOT_EXT_SIZE) as u64;
+        let mut last_version = base_table.data.descriptor.get_version();
+        let header_size = size_of::<Vdfs4ExtendedTable>() as u64;
+        let record_size = size_of::<Vdfs4ExtendedRecord>() as u64;
+
+        let mut extended_tables = Vec::new();
+        for _extended_table_index in 0..VDFS4_SNAPSHOT_EXT_TABLES {
+            let extended_table: DataPointer<Vdfs4ExtendedTable> =
+                self.data_source.read_at(extended_table_offset)?;
+            let descriptor = &extended_table.data.descriptor;
+            let records_count = extended_table.data.records_count as u64;
+
+            if !descriptor.check_signature(VDFS4_SNAPSHOT_EXTENDED_TABLE)
+                || descriptor.mount_count != base_table.data.descriptor.mount_count
+                || descriptor.get_version() <= last_version
+                || header_size + records_count * record_size > descriptor.checksum_offset
+                || !self.check_crc32_of_snapshot_descriptor(extended_table_offset, descriptor)?
             {
                 break;
             }
+
+            let mut records = Vec::with_capacity(records_count as usize);
+            for record_index in 0..records_count {
+                let record: Vdfs4ExtendedRecord = self
+                    .data_source
+                    .read_at(extended_table_offset + header_size + record_index * record_size)?
+                    .data;
+                records.push(record);
+            }
+
+            last_version = descriptor.get_version();
+            extended_table_offset += size_ceil_to_block(
+                descriptor.checksum_offset as usize + CRC32_SIZE,
+                VDFS4_SNAPSHOT_EXT_SIZE,
+            ) as u64;
+            extended_tables.push((extended_table, records));
+        }
+        Ok(extended_tables)
+    }
+
+    /// Node locations of the tree from the extended tables, the newest table wins.
+    fn get_translation_overlay(&self, btree_type: BtreeType) -> TranslationOverlay {
+        let mut translation_overlay = TranslationOverlay::new();
+        for (extended_table, records) in &self.current_extended_tables {
+            let descriptor = &extended_table.data.descriptor;
+            for record in records {
+                if record.object_id != btree_type as u64 {
+                    continue;
+                }
+                translation_overlay.insert(
+                    record.table_index,
+                    Vdfs4BaseTableRecord {
+                        meta_iblock: record.meta_iblock,
+                        sync_count: descriptor.sync_count,
+                        mount_count: descriptor.mount_count as u32,
+                    },
+                );
+            }
         }
-        todo!("Extent tables not implemented yet");
+        translation_overlay
     }
 
     fn blocks_to_bytes(&self, blocks: u64) -> u64 {
@@ -227,10 +449,10 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
     fn calc_crc32(&self, data: &(impl Encode + HasCrc32)) -> Result<u32, VdfsError> {
         let encoded = self.data_source.serialize(data)?;
         let slice_without_crc32 = data.get_body_without_crc32(encoded.as_slice());
-        Ok(vdfs_crc::crc32(&slice_without_crc32))
+        Ok(vdfs_crc::crc32(slice_without_crc32))
     }
 
-    fn validate_crc32(&self, data: &(impl Encode + HasCrc32)) -> Result<bool, VdfsError> {
+    pub fn validate_crc32(&self, data: &(impl Encode + HasCrc32)) -> Result<bool, VdfsError> {
         let crc32_from_data = data.get_crc32();
         let crc32_calculated = self.calc_crc32(data)?;
         Ok(crc32_from_data == crc32_calculated)
@@ -241,11 +463,26 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
             .current_base_table
             .ok_or(VdfsError::BaseTableIsMissing())?;
         let data_source = self.data_source;
-        let btree = CatalogTree::new(data_source, self.super_blocks, base_table)?;
+        let btree = CatalogTree::new(
+            data_source,
+            self.super_blocks,
+            base_table,
+            self.get_translation_overlay(BtreeType::CatalogTree),
+        )?;
         self.catalog_btree = Some(btree);
-        let btree = ExtentTree::new(&self.data_source, self.super_blocks, base_table)?;
+        let btree = ExtentTree::new(
+            self.data_source,
+            self.super_blocks,
+            base_table,
+            self.get_translation_overlay(BtreeType::ExtentsTree),
+        )?;
         self.extent_btree = Some(btree);
-        let btree = XattrTree::new(&self.data_source, self.super_blocks, base_table)?;
+        let btree = XattrTree::new(
+            self.data_source,
+            self.super_blocks,
+            base_table,
+            self.get_translation_overlay(BtreeType::XAttrTree),
+        )?;
         self.xattr_btree = Some(btree);
         Ok(())
     }
@@ -262,3 +499,92 @@ impl From<BtreeError> for VdfsError {
         VdfsError::BtreeError(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{test_image::*, *};
+
+    const SUPER_BLOCK_SIZE: u64 = size_of::<Vdfs4SuperBlock>() as u64;
+
+    /// Changes a byte in the middle of the superblock at `position`, so that its CRC does not match.
+    fn damage_super_block(data_source: &TestDataSource, position: u64) {
+        data_source.write_at(&0xffu8, position + 100).unwrap();
+    }
+
+    #[test]
+    fn damaged_primary_super_block_is_replaced_by_copy() {
+        let data_source = TestImage::new().build();
+        damage_super_block(&data_source, SUPER_BLOCK_OFFSET);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert_eq!(vdfs.get_used_super_block_copy(), Some(0));
+        assert_eq!(vdfs.get_block_size(), BLOCK_SIZE);
+
+        damage_super_block(&data_source, 0);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert_eq!(vdfs.get_used_super_block_copy(), Some(1));
+        assert!(vdfs.lookup("/").is_ok());
+    }
+
+    #[test]
+    fn all_super_blocks_damaged() {
+        let data_source = TestImage::new().build();
+        for copy_index in 0..3 {
+            damage_super_block(&data_source, copy_index * SUPER_BLOCK_SIZE);
+        }
+        assert!(matches!(
+            Vdfs::open(&data_source),
+            Err(VdfsError::SuperBlockChecksumMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn valid_super_block_is_used() {
+        let data_source = TestImage::new().build();
+        // A damaged copy does not matter while the primary superblock is valid
+        damage_super_block(&data_source, 0);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert_eq!(vdfs.get_used_super_block_copy(), None);
+        assert!(matches!(
+            vdfs.get_layout_version(),
+            Some(VdfsLayoutVersion::V2007)
+        ));
+    }
+
+    #[test]
+    fn super_block_layout_versions() {
+        let mut image = TestImage::new();
+        image.super_block.layout_version = *VDFS4_LAYOUT_VERSION_2006;
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert!(matches!(
+            vdfs.get_layout_version(),
+            Some(VdfsLayoutVersion::V2006)
+        ));
+
+        let mut image = TestImage::new();
+        image.super_block.layout_version = *b"2099";
+        let data_source = image.build();
+        assert!(matches!(
+            Vdfs::open(&data_source),
+            Err(VdfsError::UnsupportedLayoutVersion(_))
+        ));
+
+        let mut image = TestImage::new();
+        image.super_block.signature = *b"VDFX";
+        let data_source = image.build();
+        assert!(matches!(
+            Vdfs::open(&data_source),
+            Err(VdfsError::SuperBlockSignatureMismatch())
+        ));
+    }
+
+    #[test]
+    fn damaged_ext_super_block() {
+        let data_source = TestImage::new().build();
+        damage_super_block(&data_source, SUPER_BLOCK_OFFSET + SUPER_BLOCK_SIZE);
+        assert!(matches!(
+            Vdfs::open(&data_source),
+            Err(VdfsError::ExtendedSuperBlockChecksumMismatch(_, _))
+        ));
+    }
+}
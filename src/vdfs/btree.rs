@@ -1,10 +1,11 @@
-use std::mem::size_of;
+use std::{cmp::Ordering, collections::BTreeMap, mem::size_of};
 
 use bincode::Decode;
 
 use super::{data_source::*, layout::consts::*, layout::*};
 
 pub mod catalog;
+pub mod check;
 pub mod extent;
 pub mod xattr;
 
@@ -34,12 +35,16 @@ pub struct BnodeRecordInfo<T: VdfsBtreeKey> {
 
 const VDFS4_BTREE_LEAF_LVL: u16 = 1;
 
+/// Node locations from the extended tables, they take precedence over the base table.
+pub type TranslationOverlay = BTreeMap<u64, Vdfs4BaseTableRecord>;
+
 #[derive(Debug)]
 pub struct VdfsBtree<'a, S: DataSourceSource> {
     data_source: &'a DataSource<S>,
 
     super_blocks: Vdfs4SuperBlocks,
     base_table: DataPointer<Vdfs4BaseTable>,
+    translation_overlay: TranslationOverlay,
 
     node_size_bytes: u64,
     block_size: u64,
@@ -59,17 +64,17 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         data_source: &'b DataSource<S>,
         super_blocks: Vdfs4SuperBlocks,
         base_table: DataPointer<Vdfs4BaseTable>,
+        translation_overlay: TranslationOverlay,
         btree_type: BtreeType,
     ) -> Result<VdfsBtree<'a, S>, BtreeError> {
-        let log_blocks_in_leb =
-            super_blocks.super_block.log_super_page_size - super_blocks.super_block.log_block_size;
-        let block_size: u64 = 1 << super_blocks.super_block.log_block_size;
-        let node_size_bytes = (1 << log_blocks_in_leb) * block_size;
+        let block_size = super_blocks.super_block.get_block_size();
+        let node_size_bytes = super_blocks.super_block.get_super_page_size();
 
         let mut btree = VdfsBtree {
             data_source,
             super_blocks,
             base_table,
+            translation_overlay,
             node_size_bytes,
             block_size,
             btree_type,
@@ -103,14 +108,21 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         base_table: &DataPointer<Vdfs4BaseTable>,
         node_id: u32,
     ) -> Result<DataPointer<T>, BtreeError> {
-        let first_record_position = base_table.data.get_translated_position(
-            base_table.position,
-            &base_table.data,
-            self.btree_type,
-        );
-        let record_size = size_of::<Vdfs4BaseTableRecord>();
-        let record_position = first_record_position + record_size as u64 * node_id as u64;
-        let table_record: Vdfs4BaseTableRecord = data_source.read_at(record_position).unwrap().data;
+        let table_record: Vdfs4BaseTableRecord =
+            match self.translation_overlay.get(&(node_id as u64)) {
+                Some(overlay_record) => *overlay_record,
+                None => {
+                    let first_record_position = base_table.data.get_translated_position(
+                        base_table.position,
+                        &base_table.data,
+                        self.btree_type,
+                    );
+                    let record_size = size_of::<Vdfs4BaseTableRecord>();
+                    let record_position =
+                        first_record_position + record_size as u64 * node_id as u64;
+                    data_source.read_at(record_position)?.data
+                }
+            };
         let iblock_position = self.get_iblock_offset(table_record.meta_iblock) * self.block_size;
         let iblock_descriptor: DataPointer<T> = data_source.read_at(iblock_position)?;
 
@@ -133,7 +145,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
     }
 
     fn get_bnode_offset_offset(&self, index: u16) -> u64 {
-        self.node_size_bytes - CRC32_SIZE as u64 - size_of::<u32>() as u64 * (index + 1) as u64
+        self.node_size_bytes - CRC32_SIZE as u64 - size_of::<u32>() as u64 * (index as u64 + 1)
     }
 
     fn get_bnode_offset_position(
@@ -149,7 +161,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         bnode: &DataPointer<Vdfs4GeneralBtreeNode>,
         index: u16,
     ) -> Result<u32, BtreeError> {
-        if index > bnode.data.get_last_record_index() {
+        if index >= bnode.data.recs_count {
             return Err(BtreeError::BnodeRecordIndexOutOfBounds(index));
         }
 
@@ -168,7 +180,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         bnode: &DataPointer<Vdfs4GeneralBtreeNode>,
         index: u16,
     ) -> Result<u32, BtreeError> {
-        if index > bnode.data.get_last_record_index() {
+        if index >= bnode.data.recs_count {
             return Err(BtreeError::BnodeRecordIndexOutOfBounds(index));
         }
 
@@ -226,7 +238,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         let mut bnode_id = head_bnode.data.root_bnode_id;
         let mut bnode_record_info = self.traverse_level(key, bnode_id)?;
 
-        for level in ((till_level + 1)..=max_tree_level).rev() {
+        for _level in ((till_level + 1)..=max_tree_level).rev() {
             let index_value: GenericIndexValue = bnode_record_info
                 .key
                 .get_record_value(self.data_source)?
@@ -245,7 +257,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
     ) -> Result<BnodeRecordInfo<T>, BtreeError> {
         let mut bnode: DataPointer<Vdfs4GeneralBtreeNode> =
             self.read_base_table_record(self.data_source, &self.base_table, start_bnode_id)?;
-        let mut bnode_buffer = self
+        let bnode_buffer = self
             .data_source
             .read_bytes_at(bnode.position, self.node_size_bytes)?;
         let (mut bnode_record_index, mut bnode_record): (u16, DataPointer<T>) =
@@ -255,21 +267,29 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         while bnode_record_index == bnode.data.get_last_record_index()
             && bnode.data.next_node_id != 0
         {
-            bnode = self.read_base_table_record(
+            let next_bnode: DataPointer<Vdfs4GeneralBtreeNode> = self.read_base_table_record(
                 self.data_source,
                 &self.base_table,
                 bnode.data.next_node_id,
             )?;
-            bnode_buffer = self
+            let next_bnode_buffer = self
                 .data_source
-                .read_bytes_at(bnode.position, self.node_size_bytes)?;
-            (bnode_record_index, bnode_record) =
-                self.binary_search_in_bnode(&bnode_buffer, key, &bnode)?;
+                .read_bytes_at(next_bnode.position, self.node_size_bytes)?;
+            match self.binary_search_in_bnode(&next_bnode_buffer, key, &next_bnode) {
+                Ok((index, record)) => {
+                    bnode = next_bnode;
+                    bnode_record_index = index;
+                    bnode_record = record;
+                }
+                // The key is between the last record of this node and the first one of the next
+                Err(BtreeError::LeftRecordKeyIsHigherThanSearchKey()) => break,
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(BnodeRecordInfo {
             key: bnode_record,
-            bnode: bnode,
+            bnode,
             record_index: bnode_record_index,
         })
     }
@@ -281,47 +301,96 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
         bnode: &DataPointer<Vdfs4GeneralBtreeNode>,
     ) -> Result<(u16, DataPointer<T>), BtreeError> {
         let mut left_index: u16 = 0;
-        let mut right_index = bnode.data.recs_count - 1;
-
         let mut left_record: DataPointer<T> =
-            self.get_bnode_record_from_buffer(&bnode_buffer, bnode, left_index)?;
-        if left_index == right_index || left_record.data == *key {
-            return Ok((left_index, left_record));
-        } else if left_record.data > *key {
+            self.get_bnode_record_from_buffer(bnode_buffer, bnode, left_index)?;
+        let mut right_index = bnode.data.get_last_record_index();
+
+        let left_ordering = self.compare_keys(&left_record.data, key);
+        if left_ordering == Ordering::Greater {
             return Err(BtreeError::LeftRecordKeyIsHigherThanSearchKey());
+        } else if left_index == right_index || left_ordering == Ordering::Equal {
+            return Ok((left_index, left_record));
         }
 
-        let mut record = self.get_bnode_record_from_buffer(&bnode_buffer, bnode, right_index)?;
-        if record.data == *key {
+        let mut record = self.get_bnode_record_from_buffer(bnode_buffer, bnode, right_index)?;
+        if self.compare_keys(&record.data, key) != Ordering::Greater {
             return Ok((right_index, record));
         }
 
         while left_index < right_index - 1 {
-            let middle_index = left_index + (right_index - left_index + 1) / 2;
-            record = self.get_bnode_record_from_buffer(&bnode_buffer, bnode, middle_index)?;
+            let middle_index = left_index + (right_index - left_index).div_ceil(2);
+            record = self.get_bnode_record_from_buffer(bnode_buffer, bnode, middle_index)?;
 
-            match record.data.partial_cmp(key).unwrap() {
-                std::cmp::Ordering::Less => {
+            match self.compare_keys(&record.data, key) {
+                Ordering::Less => {
                     left_index = middle_index;
                     left_record = record;
                 }
-                std::cmp::Ordering::Equal => return Ok((middle_index, record)),
-                std::cmp::Ordering::Greater => right_index = middle_index,
+                Ordering::Equal => return Ok((middle_index, record)),
+                Ordering::Greater => right_index = middle_index,
             }
         }
 
-        return Ok((left_index, left_record));
+        Ok((left_index, left_record))
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.super_blocks.super_block.case_insensitive
+    }
+
+    /// Compares keys in the order of the tree, which depends on the volume collation.
+    pub fn compare_keys<T: VdfsBtreeKey>(&self, first: &T, second: &T) -> Ordering {
+        first
+            .compare(second, self.is_case_insensitive())
+            .unwrap_or(Ordering::Equal)
     }
 
     pub fn find<T: VdfsBtreeKey>(&self, key: &T) -> Result<BnodeRecordInfo<T>, BtreeError> {
         self.traverse(key, VDFS4_BTREE_LEAF_LVL)
     }
 
+    /// Like find, but returns the first record of the tree when all records are higher than the key.
+    pub fn find_or_first<T: VdfsBtreeKey>(
+        &self,
+        key: &T,
+    ) -> Result<BnodeRecordInfo<T>, BtreeError> {
+        match self.find(key) {
+            Err(BtreeError::LeftRecordKeyIsHigherThanSearchKey()) => self.first_record(),
+            result => result,
+        }
+    }
+
+    pub fn first_record<T: VdfsBtreeKey>(&self) -> Result<BnodeRecordInfo<T>, BtreeError> {
+        let head_bnode = self.head_node.as_ref().unwrap();
+        let mut bnode: DataPointer<Vdfs4GeneralBtreeNode> =
+            self.get_bnode(head_bnode.data.root_bnode_id)?;
+
+        for _level in ((VDFS4_BTREE_LEAF_LVL + 1)..=head_bnode.data.btree_height).rev() {
+            let index_record: DataPointer<T> = self.get_bnode_record(&bnode, 0)?;
+            let index_value: GenericIndexValue =
+                index_record.get_record_value(self.data_source)?.data;
+            bnode = self.get_bnode(index_value.node_id)?;
+        }
+
+        Ok(BnodeRecordInfo {
+            key: self.get_bnode_record(&bnode, 0)?,
+            bnode,
+            record_index: 0,
+        })
+    }
+
+    pub fn is_empty(&self) -> Result<bool, BtreeError> {
+        let head_bnode = self.head_node.as_ref().unwrap();
+        let root_bnode: DataPointer<Vdfs4GeneralBtreeNode> =
+            self.get_bnode(head_bnode.data.root_bnode_id)?;
+        Ok(root_bnode.data.recs_count == 0)
+    }
+
     pub fn records_iter<T: VdfsBtreeKey>(
         &self,
         start_bnode_id: u32,
         start_index: u16,
-    ) -> Result<BtreeRecordsIterator<S, T>, BtreeError> {
+    ) -> Result<BtreeRecordsIterator<'_, S, T>, BtreeError> {
         let bnode: DataPointer<Vdfs4GeneralBtreeNode> = self.get_bnode(start_bnode_id).unwrap();
         let first_record: DataPointer<T> = self.get_bnode_record(&bnode, start_index).unwrap();
 
@@ -329,7 +398,7 @@ impl<'a, 'b: 'a, S: DataSourceSource> VdfsBtree<'a, S> {
             btree: self,
             bnode_record_info: BnodeRecordInfo {
                 key: first_record,
-                bnode: bnode,
+                bnode,
                 record_index: start_index,
             },
             initial_state: true,
@@ -361,14 +430,14 @@ impl<'a, S: DataSourceSource, T: VdfsBtreeKey> Iterator for BtreeRecordsIterator
             }
         }
         self.initial_state = false;
-        return Some(
+        Some(
             self.btree
                 .get_bnode_record(
                     &self.bnode_record_info.bnode,
                     self.bnode_record_info.record_index,
                 )
                 .unwrap(),
-        );
+        )
     }
 }
 
@@ -386,7 +455,26 @@ impl<T: VdfsBtreeKey> DataPointer<T> {
         let position = self.position + self.data.get_value_offset();
         data_source
             .read_at(position)
-            .map_err(|e| BtreeError::DataSourceError(e))
+            .map_err(BtreeError::DataSourceError)
+    }
+}
+
+impl<T: VdfsBtreeKey> DataPointer<T> {
+    /// Reads the record value as bytes, its length is the record length without the key.
+    pub fn get_record_value_bytes<S: DataSourceSource>(
+        &self,
+        data_source: &DataSource<S>,
+    ) -> Result<Vec<u8>, BtreeError> {
+        let offset = self.data.get_value_offset();
+        let record_len = self.data.get_generic_key().record_len as u64;
+
+        if offset > VDFS4_KEY_MAX_LEN || record_len < offset {
+            return Err(BtreeError::BnodeRecordValueOffsetOutOfBounds(offset));
+        }
+
+        data_source
+            .read_bytes_at(self.position + offset, record_len - offset)
+            .map_err(BtreeError::DataSourceError)
     }
 }
 
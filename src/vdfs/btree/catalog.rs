@@ -10,19 +10,75 @@ impl<'a, 'b: 'a, S: DataSourceSource> CatalogTree<'a, S> {
         data_source: &'b DataSource<S>,
         super_blocks: Vdfs4SuperBlocks,
         base_table: DataPointer<Vdfs4BaseTable>,
+        translation_overlay: TranslationOverlay,
     ) -> Result<CatalogTree<'a, S>, BtreeError> {
         let btree = VdfsBtree::new(
             data_source,
             super_blocks,
             base_table,
+            translation_overlay,
             BtreeType::CatalogTree,
         )?;
         Ok(CatalogTree { btree })
     }
 
+    /// Returns the record of the root folder. It is the only record with RootDirObject parent,
+    /// so it always precedes the first child of root.
+    pub fn root_record(&self) -> Result<Option<DataPointer<Vdfs4CatTreeKey>>, BtreeError> {
+        let key = Vdfs4CatTreeKey::child_of_root();
+        let record = self.btree.find(&key)?.key;
+        if record.data.object_id == SpecialInodeIds::Root as u64
+            && record.data.parent_id == SpecialInodeIds::RootDirObject as u64
+        {
+            Ok(Some(record))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Iterates records starting from the first one that is not less than the key.
+    pub fn records_from(
+        &self,
+        key: Vdfs4CatTreeKey,
+    ) -> Result<impl Iterator<Item = DataPointer<Vdfs4CatTreeKey>> + '_, BtreeError> {
+        let node = self.btree.find(&key)?;
+        Ok(self
+            .btree
+            .records_iter(node.bnode.data.node_id, node.record_index)?
+            .skip_while(move |record| {
+                self.btree.compare_keys(&record.data, &key) == Ordering::Less
+            }))
+    }
+
+    /// Finds the record `name` inside the folder `parent_id`.
+    pub fn find_record(
+        &self,
+        parent_id: u64,
+        name: &[u8],
+    ) -> Result<Option<DataPointer<Vdfs4CatTreeKey>>, BtreeError> {
+        let key = Vdfs4CatTreeKey::from_parent_and_name(parent_id, name);
+        let record = self.records_from(key)?.next().filter(|record| {
+            record
+                .data
+                .has_parent_and_name(parent_id, name, self.btree.is_case_insensitive())
+        });
+        Ok(record)
+    }
+
+    /// Finds the nameless record of an inode that is stored as a child of itself,
+    /// like hard linked and orphan inodes are.
+    pub fn find_inode_record(
+        &self,
+        object_id: u64,
+    ) -> Result<Option<DataPointer<Vdfs4CatTreeKey>>, BtreeError> {
+        Ok(self
+            .find_record(object_id, &[])?
+            .filter(|record| record.data.object_id == object_id))
+    }
+
     pub fn all_records_iterator(
         &self,
-    ) -> Result<BtreeRecordsIterator<S, Vdfs4CatTreeKey>, BtreeError> {
+    ) -> Result<BtreeRecordsIterator<'_, S, Vdfs4CatTreeKey>, BtreeError> {
         let key = Vdfs4CatTreeKey::child_of_root();
         let root_child_node = self.btree.find(&key)?;
         self.btree.records_iter(
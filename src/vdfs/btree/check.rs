@@ -0,0 +1,282 @@
+use std::collections::BTreeSet;
+
+use super::*;
+
+/// Inconsistency found in a node of a B-tree, the first field is the node id.
+#[derive(Debug)]
+pub enum BnodeFinding {
+    /// Node cannot be read, has a bad signature or a version that differs from the base table.
+    Unreadable(u32, BtreeError),
+    /// Node is referenced by more than one index record.
+    ReferencedTwice(u32),
+    /// Node id stored in the descriptor differs from the one it was reached by.
+    NodeIdMismatch(u32, u32),
+    /// Record offsets do not fit into the node.
+    RecordsCountOutOfBounds(u32, u16),
+    /// Record index and its offset, the index equal to recs_count is the free space offset.
+    RecordOffsetOutOfBounds(u32, u16, u32),
+    /// Record index and its length, the record overlaps the free space.
+    RecordLengthOutOfBounds(u32, u16, u16),
+    RecordUnreadable(u32, u16),
+    /// Stored and computed free space.
+    FreeSpaceMismatch(u32, u16, u64),
+    /// Record key is not higher than the key of the previous record.
+    KeysOutOfOrder(u32, u16),
+    /// First key of the node is not higher than the last key of the previous node of the level.
+    KeysOutOfOrderAcrossNodes(u32, u32),
+    /// next_node_id of the node and the id of the node that actually follows it on the level.
+    SiblingLinkMismatch(u32, u32, u32),
+}
+
+struct CheckedBnode<T: VdfsBtreeKey> {
+    descriptor: Vdfs4GeneralBtreeNode,
+    records: Vec<DataPointer<T>>,
+    child_node_ids: Vec<u32>,
+}
+
+impl<'a, S: DataSourceSource> VdfsBtree<'a, S> {
+    /// Walks the tree level by level from the root without stopping on bad nodes,
+    /// every leaf record that could be decoded is passed to `visit_record` in the tree order.
+    pub fn check_nodes<T: VdfsBtreeKey>(
+        &self,
+        mut visit_record: impl FnMut(DataPointer<T>),
+    ) -> Vec<BnodeFinding> {
+        let head_bnode = self.head_node.as_ref().unwrap();
+        let mut findings = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut level_node_ids = vec![head_bnode.data.root_bnode_id];
+
+        for level in (VDFS4_BTREE_LEAF_LVL..=head_bnode.data.btree_height).rev() {
+            let mut child_node_ids = Vec::new();
+            // Id, next_node_id and the last key of the previous node of the level
+            let mut previous: Option<(u32, u32, Option<DataPointer<T>>)> = None;
+
+            for node_id in level_node_ids {
+                if !visited.insert(node_id) {
+                    findings.push(BnodeFinding::ReferencedTwice(node_id));
+                    continue;
+                }
+                let Some(mut bnode) =
+                    self.check_bnode::<T>(node_id, level > VDFS4_BTREE_LEAF_LVL, &mut findings)
+                else {
+                    continue;
+                };
+
+                if let Some((previous_id, previous_next_id, previous_key)) = previous {
+                    if previous_next_id != node_id {
+                        findings.push(BnodeFinding::SiblingLinkMismatch(
+                            previous_id,
+                            previous_next_id,
+                            node_id,
+                        ));
+                    }
+                    if let (Some(previous_key), Some(first_record)) =
+                        (previous_key, bnode.records.first())
+                    {
+                        if self.compare_keys(&previous_key.data, &first_record.data)
+                            != Ordering::Less
+                        {
+                            findings.push(BnodeFinding::KeysOutOfOrderAcrossNodes(
+                                previous_id,
+                                node_id,
+                            ));
+                        }
+                    }
+                }
+
+                child_node_ids.append(&mut bnode.child_node_ids);
+                // Keys are not cloneable, so the last one is read again for the next node
+                let last_key = bnode
+                    .records
+                    .last()
+                    .and_then(|record| self.data_source.read_at(record.position).ok());
+                if level == VDFS4_BTREE_LEAF_LVL {
+                    bnode.records.into_iter().for_each(&mut visit_record);
+                }
+                previous = Some((node_id, bnode.descriptor.next_node_id, last_key));
+            }
+
+            if let Some((previous_id, previous_next_id, _)) = previous {
+                if previous_next_id != VDFS4_INVALID_NODE_ID as u32 {
+                    findings.push(BnodeFinding::SiblingLinkMismatch(
+                        previous_id,
+                        previous_next_id,
+                        VDFS4_INVALID_NODE_ID as u32,
+                    ));
+                }
+            }
+            level_node_ids = child_node_ids;
+        }
+
+        findings
+    }
+
+    /// Checks the node layout and decodes its records, returns None when records cannot be located.
+    /// Offsets of the records are stored backwards before the CRC at the end of the node,
+    /// the extra offset after the last record points to the free space that ends at the offsets.
+    fn check_bnode<T: VdfsBtreeKey>(
+        &self,
+        node_id: u32,
+        is_index_node: bool,
+        findings: &mut Vec<BnodeFinding>,
+    ) -> Option<CheckedBnode<T>> {
+        let bnode: DataPointer<Vdfs4GeneralBtreeNode> = match self.get_bnode(node_id) {
+            Ok(bnode) => bnode,
+            Err(e) => {
+                findings.push(BnodeFinding::Unreadable(node_id, e));
+                return None;
+            }
+        };
+        if bnode.data.node_id != node_id {
+            findings.push(BnodeFinding::NodeIdMismatch(node_id, bnode.data.node_id));
+        }
+        let buffer = match self
+            .data_source
+            .read_bytes_at(bnode.position, self.node_size_bytes)
+        {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                findings.push(BnodeFinding::Unreadable(node_id, e.into()));
+                return None;
+            }
+        };
+
+        let header_size = size_of::<Vdfs4GeneralBtreeNode>() as u64;
+        let recs_count = bnode.data.recs_count;
+        let offsets_size = size_of::<u32>() as u64 * (recs_count as u64 + 1);
+        let Some(offsets_start) = self
+            .node_size_bytes
+            .checked_sub(CRC32_SIZE as u64 + offsets_size)
+            .filter(|offsets_start| *offsets_start >= header_size)
+        else {
+            findings.push(BnodeFinding::RecordsCountOutOfBounds(node_id, recs_count));
+            return None;
+        };
+
+        let free_space_offset = self.read_bnode_offset(&buffer, recs_count);
+        let records_end = if (header_size..=offsets_start).contains(&(free_space_offset as u64)) {
+            let free_space = offsets_start - free_space_offset as u64;
+            if free_space != bnode.data.free_space as u64 {
+                findings.push(BnodeFinding::FreeSpaceMismatch(
+                    node_id,
+                    bnode.data.free_space,
+                    free_space,
+                ));
+            }
+            free_space_offset as u64
+        } else {
+            findings.push(BnodeFinding::RecordOffsetOutOfBounds(
+                node_id,
+                recs_count,
+                free_space_offset,
+            ));
+            offsets_start
+        };
+
+        let mut records: Vec<DataPointer<T>> = Vec::with_capacity(recs_count as usize);
+        let mut child_node_ids = Vec::new();
+        for index in 0..recs_count {
+            let offset = self.read_bnode_offset(&buffer, index);
+            if (offset as u64) < header_size || offset as u64 >= records_end {
+                findings.push(BnodeFinding::RecordOffsetOutOfBounds(
+                    node_id, index, offset,
+                ));
+                continue;
+            }
+            let record: T = match self.data_source.deserialize(&buffer[offset as usize..]) {
+                Ok(record) => record,
+                Err(_) => {
+                    findings.push(BnodeFinding::RecordUnreadable(node_id, index));
+                    continue;
+                }
+            };
+            let record_len = record.get_generic_key().record_len;
+            if offset as u64 + record_len as u64 > records_end {
+                findings.push(BnodeFinding::RecordLengthOutOfBounds(
+                    node_id, index, record_len,
+                ));
+                continue;
+            }
+
+            if is_index_node {
+                let value_offset = offset as u64 + record.get_value_offset();
+                let value_end = value_offset + size_of::<GenericIndexValue>() as u64;
+                let index_value: Option<GenericIndexValue> = (value_end <= records_end)
+                    .then(|| {
+                        self.data_source
+                            .deserialize(&buffer[value_offset as usize..])
+                            .ok()
+                    })
+                    .flatten();
+                match index_value {
+                    Some(index_value) => child_node_ids.push(index_value.node_id),
+                    None => {
+                        findings.push(BnodeFinding::RecordUnreadable(node_id, index));
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(previous_record) = records.last() {
+                if self.compare_keys(&previous_record.data, &record) != Ordering::Less {
+                    findings.push(BnodeFinding::KeysOutOfOrder(node_id, index));
+                }
+            }
+            records.push(DataPointer {
+                data: record,
+                position: bnode.position + offset as u64,
+            });
+        }
+
+        Some(CheckedBnode {
+            descriptor: bnode.data,
+            records,
+            child_node_ids,
+        })
+    }
+
+    /// Offsets that do not fit into the buffer are returned as zero, which is never valid.
+    fn read_bnode_offset(&self, buffer: &[u8], index: u16) -> u32 {
+        let offset_offset = self.get_bnode_offset_offset(index) as usize;
+        self.data_source
+            .deserialize(&buffer[offset_offset..])
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::{test_image::*, *};
+
+    const NEXT_NODE_ID_OFFSET: u64 = 24;
+
+    #[test]
+    fn check_sibling_links() {
+        let mut image = TestImage::new();
+        image.records_per_leaf = 2;
+        for index in 0..6 {
+            image.add_folder(1, &format!("folder{}", index), 10 + index, folder_record());
+        }
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let btree = &vdfs.get_catalog_tree().unwrap().btree;
+        assert!(btree
+            .check_nodes(|_: DataPointer<Vdfs4CatTreeKey>| {})
+            .is_empty());
+
+        // The first leaf skips the second one
+        let first_leaf = btree.get_bnode::<Vdfs4GeneralBtreeNode>(2).unwrap();
+        data_source
+            .write_at(&4u32, first_leaf.position + NEXT_NODE_ID_OFFSET)
+            .unwrap();
+        let mut records_count = 0;
+        let findings = btree.check_nodes(|_: DataPointer<Vdfs4CatTreeKey>| records_count += 1);
+        assert!(matches!(
+            findings[..],
+            [BnodeFinding::SiblingLinkMismatch(2, 4, 3)]
+        ));
+        // Records of all leaves are still visited
+        assert_eq!(records_count, 7);
+    }
+}
@@ -10,20 +10,48 @@ impl<'a, S: DataSourceSource> ExtentTree<'a, S> {
         data_source: &'a DataSource<S>,
         super_blocks: Vdfs4SuperBlocks,
         base_table: DataPointer<Vdfs4BaseTable>,
+        translation_overlay: TranslationOverlay,
     ) -> Result<ExtentTree<'a, S>, BtreeError> {
         let btree = VdfsBtree::new(
             data_source,
             super_blocks,
             base_table,
+            translation_overlay,
             BtreeType::ExtentsTree,
         )?;
         Ok(ExtentTree { btree })
     }
 
+    /// Finds the extent that contains the file logical block.
+    pub fn find_extent(
+        &self,
+        object_id: u64,
+        iblock: u64,
+    ) -> Result<Option<Vdfs4Iextent>, BtreeError> {
+        let key = Vdfs4ExtTreeKey::from_object_id_and_iblock(object_id, iblock);
+        let record = match self.btree.find(&key) {
+            Ok(record_info) => record_info.key,
+            Err(BtreeError::LeftRecordKeyIsHigherThanSearchKey()) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if record.data.object_id != object_id {
+            return Ok(None);
+        }
+        // Record value is the extent that follows the key
+        let extent: Vdfs4Extent = record.get_record_value(self.btree.data_source)?.data;
+        if record.data.iblock + extent.length <= iblock {
+            return Ok(None);
+        }
+        Ok(Some(Vdfs4Iextent {
+            extent,
+            iblock: record.data.iblock,
+        }))
+    }
+
     pub fn records_iterator(
         &self,
         first_object_id: u64,
-    ) -> Result<BtreeRecordsIterator<S, Vdfs4ExtTreeKey>, BtreeError> {
+    ) -> Result<BtreeRecordsIterator<'_, S, Vdfs4ExtTreeKey>, BtreeError> {
         let key = Vdfs4ExtTreeKey::from_object_id(first_object_id);
         let root_child_node = self.btree.find(&key)?;
         self.btree.records_iter(
@@ -10,8 +10,139 @@ impl<'a, S: DataSourceSource> XattrTree<'a, S> {
         data_source: &'a DataSource<S>,
         super_blocks: Vdfs4SuperBlocks,
         base_table: DataPointer<Vdfs4BaseTable>,
+        translation_overlay: TranslationOverlay,
     ) -> Result<XattrTree<'a, S>, BtreeError> {
-        let btree = VdfsBtree::new(data_source, super_blocks, base_table, BtreeType::XAttrTree)?;
+        let btree = VdfsBtree::new(
+            data_source,
+            super_blocks,
+            base_table,
+            translation_overlay,
+            BtreeType::XAttrTree,
+        )?;
         Ok(XattrTree { btree })
     }
+
+    /// Returns records of the object in the on-disk order.
+    pub fn records(
+        &self,
+        object_id: u64,
+    ) -> Result<Vec<DataPointer<Vdfs4XattrTreeKey>>, BtreeError> {
+        if self.btree.is_empty()? {
+            return Ok(Vec::new());
+        }
+        let key = Vdfs4XattrTreeKey::from_object_id_and_name(object_id, &[]);
+        let start_record = self.btree.find_or_first(&key)?;
+        let records = self
+            .btree
+            .records_iter(start_record.bnode.data.node_id, start_record.record_index)?
+            .skip_while(|record: &DataPointer<Vdfs4XattrTreeKey>| record.data.object_id < object_id)
+            .take_while(|record| record.data.object_id == object_id)
+            .collect();
+        Ok(records)
+    }
+
+    /// Names of the extended attributes of the object.
+    pub fn list(&self, object_id: u64) -> Result<Vec<String>, BtreeError> {
+        Ok(self
+            .records(object_id)?
+            .iter()
+            .map(|record| record.data.get_name_string())
+            .collect())
+    }
+
+    /// Names and raw values of the extended attributes of the object.
+    pub fn entries(&self, object_id: u64) -> Result<Vec<(String, Vec<u8>)>, BtreeError> {
+        self.records(object_id)?
+            .iter()
+            .map(|record| {
+                let value = record.get_record_value_bytes(self.btree.data_source)?;
+                Ok((record.data.get_name_string(), value))
+            })
+            .collect()
+    }
+
+    /// Raw value of the extended attribute or None if the object does not have it.
+    pub fn get(&self, object_id: u64, name: &str) -> Result<Option<Vec<u8>>, BtreeError> {
+        if name.len() > VDFS4_XATTR_NAME_MAX_LEN || self.btree.is_empty()? {
+            return Ok(None);
+        }
+        let key = Vdfs4XattrTreeKey::from_object_id_and_name(object_id, name.as_bytes());
+        let record = match self.btree.find(&key) {
+            Ok(record_info) => record_info.key,
+            Err(BtreeError::LeftRecordKeyIsHigherThanSearchKey()) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if record.data != key {
+            return Ok(None);
+        }
+        Ok(Some(record.get_record_value_bytes(self.btree.data_source)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vdfs::{test_image::*, *};
+
+    /// Attributes of the object 20 span several leaves between those of its neighbours.
+    fn xattrs_image() -> TestDataSource {
+        let mut image = TestImage::new();
+        image
+            .add_xattr(20, "user.b", b"b")
+            .add_xattr(21, "user.a", b"next")
+            .add_xattr(20, "security.SMACK64", b"_\0")
+            .add_xattr(20, "user.ab", &[1, 2, 3])
+            .add_xattr(19, "user.z", b"previous")
+            .add_xattr(20, "user.a", b"");
+        image.records_per_leaf = 2;
+        image.build()
+    }
+
+    #[test]
+    fn list_xattrs() {
+        let data_source = xattrs_image();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let xattr_tree = vdfs.get_xattr_tree().unwrap();
+
+        assert_eq!(
+            xattr_tree.list(20).unwrap(),
+            ["security.SMACK64", "user.a", "user.ab", "user.b"]
+        );
+        assert_eq!(xattr_tree.list(19).unwrap(), ["user.z"]);
+        assert!(xattr_tree.list(1).unwrap().is_empty());
+        assert!(xattr_tree.list(22).unwrap().is_empty());
+        assert_eq!(
+            xattr_tree.entries(21).unwrap(),
+            [(String::from("user.a"), b"next".to_vec())]
+        );
+    }
+
+    #[test]
+    fn get_xattr() {
+        let data_source = xattrs_image();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let xattr_tree = vdfs.get_xattr_tree().unwrap();
+
+        assert_eq!(
+            xattr_tree.get(20, "security.SMACK64").unwrap(),
+            Some(b"_\0".to_vec())
+        );
+        assert_eq!(xattr_tree.get(20, "user.ab").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(xattr_tree.get(20, "user.a").unwrap(), Some(Vec::new()));
+        assert_eq!(
+            xattr_tree.get(21, "user.a").unwrap(),
+            Some(b"next".to_vec())
+        );
+        assert_eq!(xattr_tree.get(20, "user.c").unwrap(), None);
+        assert_eq!(xattr_tree.get(1, "user.a").unwrap(), None);
+        assert_eq!(xattr_tree.get(20, &"x".repeat(300)).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_xattr_tree() {
+        let data_source = TestImage::new().build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let xattr_tree = vdfs.get_xattr_tree().unwrap();
+        assert!(xattr_tree.list(1).unwrap().is_empty());
+        assert_eq!(xattr_tree.get(1, "user.a").unwrap(), None);
+    }
 }
@@ -0,0 +1,244 @@
+use std::collections::BTreeSet;
+
+use super::{btree::check::BnodeFinding, *};
+
+/// Inconsistency found by [`Vdfs::check`].
+#[derive(Debug)]
+pub enum CheckFinding {
+    Bnode(BtreeType, BnodeFinding),
+    /// Object id and the error of decoding its catalog record value.
+    CatalogRecordUnreadable(u64, VdfsError),
+    /// Object id, logical block and the error of decoding the extent record value.
+    ExtentRecordUnreadable(u64, u64, VdfsError),
+    /// Object id and the parent id that has no folder record.
+    ParentNotFound(u64, u64),
+    /// Object id, logical block and length of the extent, total_blocks_count of the fork.
+    ForkExtentOutOfRange(u64, u64, u64, u64),
+    /// Count from the extended superblock and the count of inodes in the catalog.
+    FilesCountMismatch(u64, u64),
+    FoldersCountMismatch(u64, u64),
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// Read-only consistency check of the catalog, extents and xattr trees.
+    /// Problems are collected instead of failing on the first one,
+    /// no findings means a consistent image.
+    pub fn check(&self) -> Result<Vec<CheckFinding>, VdfsError> {
+        let mut findings = Vec::new();
+        let mut folder_ids = BTreeSet::new();
+        let mut file_ids = BTreeSet::new();
+        // Object and parent ids of named records
+        let mut links = Vec::new();
+        let mut forks_blocks_counts = BTreeMap::new();
+
+        let catalog_findings =
+            self.get_catalog_tree()?
+                .btree
+                .check_nodes(|record: DataPointer<Vdfs4CatTreeKey>| {
+                    let object_id = record.data.object_id;
+                    match record.data.get_record_type() {
+                        CatalogTreeRecordType::Folder => {
+                            folder_ids.insert(object_id);
+                        }
+                        CatalogTreeRecordType::File => {
+                            file_ids.insert(object_id);
+                            match record.get_record_value(self.data_source) {
+                                Ok(file_record) => {
+                                    let file_record: Vdfs4CatalogFileRecord = file_record.data;
+                                    check_fork_extents(
+                                        &mut findings,
+                                        object_id,
+                                        &file_record.data_fork,
+                                    );
+                                    forks_blocks_counts.insert(
+                                        object_id,
+                                        file_record.data_fork.total_blocks_count,
+                                    );
+                                }
+                                Err(e) => findings.push(CheckFinding::CatalogRecordUnreadable(
+                                    object_id,
+                                    e.into(),
+                                )),
+                            }
+                        }
+                        _ => {}
+                    }
+                    // Hard linked inodes are nameless children of themselves, root has no parent
+                    if record.data.parent_id != object_id
+                        && object_id != SpecialInodeIds::Root as u64
+                    {
+                        links.push((object_id, record.data.parent_id));
+                    }
+                });
+        add_bnode_findings(&mut findings, BtreeType::CatalogTree, catalog_findings);
+
+        let extent_findings =
+            self.get_extent_tree()?
+                .btree
+                .check_nodes(|record: DataPointer<Vdfs4ExtTreeKey>| {
+                    let (object_id, iblock) = (record.data.object_id, record.data.iblock);
+                    let extent: Vdfs4Extent = match record.get_record_value(self.data_source) {
+                        Ok(extent) => extent.data,
+                        Err(e) => {
+                            findings.push(CheckFinding::ExtentRecordUnreadable(
+                                object_id,
+                                iblock,
+                                e.into(),
+                            ));
+                            return;
+                        }
+                    };
+                    // Extents of the special inodes have no catalog record
+                    if let Some(total_blocks_count) = forks_blocks_counts.get(&object_id) {
+                        if is_extent_out_of_range(iblock, extent.length, *total_blocks_count) {
+                            findings.push(CheckFinding::ForkExtentOutOfRange(
+                                object_id,
+                                iblock,
+                                extent.length,
+                                *total_blocks_count,
+                            ));
+                        }
+                    }
+                });
+        add_bnode_findings(&mut findings, BtreeType::ExtentsTree, extent_findings);
+
+        let xattr_findings = self
+            .get_xattr_tree()?
+            .btree
+            .check_nodes(|_: DataPointer<Vdfs4XattrTreeKey>| {});
+        add_bnode_findings(&mut findings, BtreeType::XAttrTree, xattr_findings);
+
+        for (object_id, parent_id) in links {
+            if !folder_ids.contains(&parent_id) {
+                findings.push(CheckFinding::ParentNotFound(object_id, parent_id));
+            }
+        }
+
+        let ext_super_block = &self.super_blocks.ext_super_block;
+        if ext_super_block.files_count != file_ids.len() as u64 {
+            findings.push(CheckFinding::FilesCountMismatch(
+                ext_super_block.files_count,
+                file_ids.len() as u64,
+            ));
+        }
+        if ext_super_block.folders_count != folder_ids.len() as u64 {
+            findings.push(CheckFinding::FoldersCountMismatch(
+                ext_super_block.folders_count,
+                folder_ids.len() as u64,
+            ));
+        }
+
+        Ok(findings)
+    }
+}
+
+fn add_bnode_findings(
+    findings: &mut Vec<CheckFinding>,
+    btree_type: BtreeType,
+    bnode_findings: Vec<BnodeFinding>,
+) {
+    findings.extend(
+        bnode_findings
+            .into_iter()
+            .map(|finding| CheckFinding::Bnode(btree_type, finding)),
+    );
+}
+
+/// Extents stored in the fork itself, unused slots have zero length.
+fn check_fork_extents(findings: &mut Vec<CheckFinding>, object_id: u64, fork: &Vdfs4Fork) {
+    for extent in &fork.extents {
+        if extent.extent.length != 0
+            && is_extent_out_of_range(extent.iblock, extent.extent.length, fork.total_blocks_count)
+        {
+            findings.push(CheckFinding::ForkExtentOutOfRange(
+                object_id,
+                extent.iblock,
+                extent.extent.length,
+                fork.total_blocks_count,
+            ));
+        }
+    }
+}
+
+fn is_extent_out_of_range(iblock: u64, length: u64, total_blocks_count: u64) -> bool {
+    iblock
+        .checked_add(length)
+        .is_none_or(|end| end > total_blocks_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    fn check_image(data_source: &TestDataSource) -> Vec<CheckFinding> {
+        Vdfs::open(data_source).unwrap().check().unwrap()
+    }
+
+    fn consistent_image() -> TestImage {
+        let mut image = TestImage::new();
+        image
+            .add_folder(1, "etc", 10, folder_record())
+            .add_regular_file(10, "hosts", 20, b"127.0.0.1 localhost")
+            .add_regular_file(1, "README", 21, &[b'r'; 5000]);
+        image
+    }
+
+    #[test]
+    fn check_consistent_image() {
+        let data_source = consistent_image().build();
+        let findings = check_image(&data_source);
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn check_counts_mismatch() {
+        let data_source = consistent_image().build();
+        let ext_super_block = Vdfs::open(&data_source)
+            .unwrap()
+            .get_super_blocks()
+            .ext_super_block;
+        update_ext_super_block(&data_source, ext_super_block, |ext_super_block| {
+            ext_super_block.files_count = 5;
+            ext_super_block.folders_count = 1;
+        });
+        let findings = check_image(&data_source);
+        assert!(matches!(
+            findings[..],
+            [
+                CheckFinding::FilesCountMismatch(5, 2),
+                CheckFinding::FoldersCountMismatch(1, 2)
+            ]
+        ));
+    }
+
+    #[test]
+    fn check_missing_parent() {
+        let mut image = consistent_image();
+        image.add_regular_file(99, "lost", 22, b"");
+        let findings = check_image(&image.build());
+        assert!(matches!(
+            findings[..],
+            [CheckFinding::ParentNotFound(22, 99)]
+        ));
+    }
+
+    #[test]
+    fn check_fork_extent_out_of_range() {
+        let mut image = consistent_image();
+        // One block long file with extents of three blocks in the fork and one in the tree
+        let mut record = file_record(FileType::Regular, 10);
+        record.data_fork.extents[0] = iextent(0, DATA_BEGIN, 3);
+        image
+            .add_extent(22, &iextent(4, DATA_BEGIN, 1))
+            .add_file(1, "truncated", 22, record);
+        let findings = check_image(&image.build());
+        assert!(matches!(
+            findings[..],
+            [
+                CheckFinding::ForkExtentOutOfRange(22, 0, 3, 1),
+                CheckFinding::ForkExtentOutOfRange(22, 4, 1, 1)
+            ]
+        ));
+    }
+}
@@ -0,0 +1,616 @@
+use libflate::{gzip, zlib};
+use md5::Md5;
+use rsa::RsaPublicKey;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{encryption::*, file::*, signature::*, *};
+
+/// Random access to the unpacked content of a compressed file.
+/// Only the chunk that covers the current position is decompressed.
+pub struct VdfsCompressedFile<'v, 'a, S: DataSourceSource> {
+    raw_file: VdfsFile<'v, 'a, S>,
+    descriptor: Vdfs4CompressedFileDescr,
+    descriptor_size: u64,
+    compression: VdfsFileCompression,
+    extents: Vec<Vdfs4CompressedExtent>,
+    position: u64,
+    cached_chunk: Option<(u64, Vec<u8>)>,
+    hashes: Vec<Vec<u8>>,
+}
+
+/// Result of checking the unpacked content against the descriptor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentVerdict {
+    Ok,
+    /// Expected and actual length
+    SizeMismatch(u64, u64),
+    /// Expected and actual CRC32
+    CrcMismatch(u32, u32),
+}
+
+/// Result of checking chunk hashes of an authenticated file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthVerdict {
+    NotAuthenticated,
+    Ok,
+    MismatchAtChunk(u64),
+}
+
+impl<'v, 'a, S: DataSourceSource> VdfsCompressedFile<'v, 'a, S> {
+    pub fn new(raw_file: VdfsFile<'v, 'a, S>) -> Result<Self, VdfsError> {
+        let extent_size = size_of::<Vdfs4CompressedExtent>() as u64;
+        let raw_file_size = raw_file.len();
+        let data_source = raw_file.get_data_source();
+
+        let (descriptor, descriptor_size) = Self::read_descriptor(&raw_file)?;
+        let compression = descriptor
+            .get_compression()
+            .ok_or(VdfsError::CannotDecompressFileWithoutCompression)?;
+        let extents_count = descriptor.extents_num as u64;
+
+        let mut extents_table_size = descriptor_size + extent_size * extents_count;
+        if let Some(signature_type) = descriptor.get_signature_type() {
+            extents_table_size += signature_type.get_signature_length();
+        }
+        if let Some(auth_type) = descriptor.get_auth() {
+            extents_table_size += auth_type.get_hash_len() * (extents_count + 1);
+        }
+        if raw_file_size < extents_table_size {
+            return Err(VdfsError::CompressedFileExtentWrongSignature);
+        }
+        let first_extent_position = raw_file_size - extents_table_size;
+        let extents_buffer =
+            raw_file.read_bytes_at(first_extent_position, extent_size * extents_count)?;
+        let mut extents = Vec::with_capacity(extents_count as usize);
+        for extent_buffer in extents_buffer.chunks(extent_size as usize) {
+            let extent: Vdfs4CompressedExtent = data_source.deserialize(extent_buffer)?;
+            if !extent.check_extent_signature() {
+                return Err(VdfsError::CompressedFileExtentWrongSignature);
+            }
+            extents.push(extent);
+        }
+
+        // Hashes follow the extents table, there is one more hash than chunks
+        let mut hashes = Vec::new();
+        if let Some(auth_type) = descriptor.get_auth() {
+            let hash_len = auth_type.get_hash_len();
+            let hashes_buffer = raw_file.read_bytes_at(
+                first_extent_position + extent_size * extents_count,
+                hash_len * (extents_count + 1),
+            )?;
+            hashes = hashes_buffer
+                .chunks(hash_len as usize)
+                .map(|hash| hash.to_vec())
+                .collect();
+        }
+
+        Ok(Self {
+            raw_file,
+            descriptor,
+            descriptor_size,
+            compression,
+            extents,
+            position: 0,
+            cached_chunk: None,
+            hashes,
+        })
+    }
+
+    /// Descriptor is at the end of the file, older layouts have a shorter one. It is decoded by
+    /// the layout version from the common tail and returned with its on-disk size.
+    fn read_descriptor(
+        raw_file: &VdfsFile<'v, 'a, S>,
+    ) -> Result<(Vdfs4CompressedFileDescr, u64), VdfsError> {
+        let data_source = raw_file.get_data_source();
+        let raw_file_size = raw_file.len();
+        let tail_size = size_of::<Vdfs4CompressedFileDescrLayout5>() as u64;
+        if raw_file_size < tail_size {
+            return Err(VdfsError::CannotDecompressFileWithoutCompression);
+        }
+        let tail: Vdfs4CompressedFileDescrLayout5 = data_source
+            .deserialize(&raw_file.read_bytes_at(raw_file_size - tail_size, tail_size)?)?;
+
+        match tail.layout_version {
+            VDFS4_COMPR_LAYOUT_VER_05 => Ok((Vdfs4CompressedFileDescr::from(tail), tail_size)),
+            VDFS4_COMPR_LAYOUT_VER_06 => {
+                let descriptor_size = size_of::<Vdfs4CompressedFileDescr>() as u64;
+                if raw_file_size < descriptor_size {
+                    return Err(VdfsError::CannotDecompressFileWithoutCompression);
+                }
+                let descriptor: Vdfs4CompressedFileDescr = data_source.deserialize(
+                    &raw_file.read_bytes_at(raw_file_size - descriptor_size, descriptor_size)?,
+                )?;
+                Ok((descriptor, descriptor_size))
+            }
+            layout_version => Err(VdfsError::UnsupportedCompressedFileLayout(layout_version)),
+        }
+    }
+
+    pub fn get_descriptor(&self) -> &Vdfs4CompressedFileDescr {
+        &self.descriptor
+    }
+
+    /// On-disk size of the descriptor, it depends on the layout version.
+    pub fn get_descriptor_size(&self) -> u64 {
+        self.descriptor_size
+    }
+
+    pub fn get_extents(&self) -> &[Vdfs4CompressedExtent] {
+        &self.extents
+    }
+
+    pub fn get_chunk_size(&self) -> u64 {
+        1 << self.descriptor.log_chunk_size
+    }
+
+    pub fn get_chunks_count(&self) -> u64 {
+        self.extents.len() as u64
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.extents
+            .iter()
+            .any(|extent| extent.has_encrypted_flag())
+    }
+
+    /// Size of the unpacked file.
+    pub fn len(&self) -> u64 {
+        self.descriptor.unpacked_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hashes of the chunks and the extra trailing hash, empty if not authenticated.
+    pub fn get_hashes(&self) -> &[Vec<u8>] {
+        &self.hashes
+    }
+
+    /// Reads and unpacks the chunk with the given index.
+    pub fn read_chunk(&self, chunk_index: u64) -> Result<Vec<u8>, VdfsError> {
+        self.unpack_chunk(chunk_index, self.read_raw_chunk(chunk_index)?)
+    }
+
+    /// Reads the chunk as it is stored, compressed and maybe encrypted.
+    pub fn read_raw_chunk(&self, chunk_index: u64) -> Result<Vec<u8>, VdfsError> {
+        let extent = self.get_chunk_extent(chunk_index)?;
+        self.raw_file
+            .read_bytes_at(extent.start, extent.len_bytes as u64)
+    }
+
+    /// Checks the hash of a chunk as it is stored, before decryption and decompression.
+    pub fn verify_raw_chunk(&self, chunk_index: u64, raw_chunk: &[u8]) -> Result<bool, VdfsError> {
+        let auth_type = match self.descriptor.get_auth() {
+            Some(auth_type) => auth_type,
+            None => return Ok(true),
+        };
+        let expected_hash = self
+            .hashes
+            .get(chunk_index as usize)
+            .ok_or(VdfsError::CompressedFileChunkNotFound(chunk_index))?;
+        Ok(calc_hash(auth_type, raw_chunk) == *expected_hash)
+    }
+
+    /// Checks hashes of all chunks, stops at the first mismatch.
+    pub fn verify_chunks(&self) -> Result<AuthVerdict, VdfsError> {
+        if self.descriptor.get_auth().is_none() {
+            return Ok(AuthVerdict::NotAuthenticated);
+        }
+        for chunk_index in 0..self.get_chunks_count() {
+            if !self.verify_raw_chunk(chunk_index, &self.read_raw_chunk(chunk_index)?)? {
+                return Ok(AuthVerdict::MismatchAtChunk(chunk_index));
+            }
+        }
+        Ok(AuthVerdict::Ok)
+    }
+
+    /// Compares the unpacked length and its CRC32 with the values from the descriptor.
+    pub fn check_content(&self, unpacked_length: u64, crc32: u32) -> ContentVerdict {
+        if unpacked_length != self.descriptor.unpacked_size {
+            ContentVerdict::SizeMismatch(self.descriptor.unpacked_size, unpacked_length)
+        } else if crc32 != self.descriptor.get_crc32() {
+            ContentVerdict::CrcMismatch(self.descriptor.get_crc32(), crc32)
+        } else {
+            ContentVerdict::Ok
+        }
+    }
+
+    /// Unpacks all chunks and checks the result, see check_content.
+    pub fn verify_content(&self) -> Result<ContentVerdict, VdfsError> {
+        let mut crc32_digest = vdfs_crc::crc32_digest();
+        let mut unpacked_length = 0;
+        for chunk_index in 0..self.get_chunks_count() {
+            let chunk = self.read_chunk(chunk_index)?;
+            crc32_digest.update(&chunk);
+            unpacked_length += chunk.len() as u64;
+        }
+        Ok(self.check_content(unpacked_length, crc32_digest.finalize()))
+    }
+
+    /// Signature is stored right before the descriptor.
+    pub fn read_signature(&self) -> Result<Option<Vec<u8>>, VdfsError> {
+        let signature_length = match self.descriptor.get_signature_type() {
+            Some(signature_type) if self.descriptor.get_auth().is_some() => {
+                signature_type.get_signature_length()
+            }
+            _ => return Ok(None),
+        };
+        if signature_length == 0 {
+            return Ok(None);
+        }
+        let signature_position = self.raw_file.len() - self.descriptor_size - signature_length;
+        Ok(Some(
+            self.raw_file
+                .read_bytes_at(signature_position, signature_length)?,
+        ))
+    }
+
+    /// The signature is made over the digest of the hash table, chunks are covered by
+    /// their hashes, see verify_chunks.
+    pub fn verify_signature(
+        &self,
+        public_key: &RsaPublicKey,
+    ) -> Result<SignatureVerdict, VdfsError> {
+        let (signature, auth_type) = match (self.read_signature()?, self.descriptor.get_auth()) {
+            (Some(signature), Some(auth_type)) => (signature, auth_type),
+            _ => return Ok(SignatureVerdict::NotSigned),
+        };
+        let hash_table = self.hashes.concat();
+        if !verify_rsa_signature(public_key, auth_type, &hash_table, &signature) {
+            return Ok(SignatureVerdict::Invalid);
+        }
+        // The signature only covers the hashes, the chunks are checked against them
+        Ok(match self.verify_chunks()? {
+            AuthVerdict::MismatchAtChunk(chunk_index) => {
+                SignatureVerdict::ChunkMismatch(chunk_index)
+            }
+            AuthVerdict::Ok | AuthVerdict::NotAuthenticated => SignatureVerdict::Ok,
+        })
+    }
+
+    /// Unpacks the chunk that was read by read_raw_chunk.
+    pub fn unpack_chunk(
+        &self,
+        chunk_index: u64,
+        chunk_buffer: Vec<u8>,
+    ) -> Result<Vec<u8>, VdfsError> {
+        let extent = self.get_chunk_extent(chunk_index)?;
+
+        let mut chunk_buffer = chunk_buffer;
+        if extent.has_encrypted_flag() {
+            let key = self
+                .raw_file
+                .get_vdfs()
+                .get_encryption_key()
+                .ok_or(VdfsError::EncryptionKeyMissing())?;
+            decrypt_chunk(
+                key,
+                &self.descriptor.aes_nonce,
+                self.descriptor.log_chunk_size,
+                chunk_index,
+                &mut chunk_buffer,
+            );
+        }
+
+        if extent.has_uncompressed_flag() {
+            Ok(chunk_buffer)
+        } else {
+            decompress_chunk(
+                &self.compression,
+                chunk_buffer.as_slice(),
+                self.get_chunk_size() as usize,
+            )
+        }
+    }
+
+    fn get_chunk_extent(&self, chunk_index: u64) -> Result<&Vdfs4CompressedExtent, VdfsError> {
+        self.extents
+            .get(chunk_index as usize)
+            .ok_or(VdfsError::CompressedFileChunkNotFound(chunk_index))
+    }
+
+    fn read_at_position(&mut self, buf: &mut [u8]) -> Result<usize, VdfsError> {
+        if self.position >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_index = self.position >> self.descriptor.log_chunk_size;
+        let offset_in_chunk =
+            (self.position - (chunk_index << self.descriptor.log_chunk_size)) as usize;
+        if !matches!(&self.cached_chunk, Some((index, _)) if *index == chunk_index) {
+            self.cached_chunk = Some((chunk_index, self.read_chunk(chunk_index)?));
+        }
+        let (_, chunk) = self.cached_chunk.as_ref().unwrap();
+        if offset_in_chunk >= chunk.len() {
+            return Err(VdfsError::DecompressionError);
+        }
+
+        let bytes_to_read = buf
+            .len()
+            .min(chunk.len() - offset_in_chunk)
+            .min((self.len() - self.position) as usize);
+        buf[..bytes_to_read]
+            .copy_from_slice(&chunk[offset_in_chunk..offset_in_chunk + bytes_to_read]);
+        Ok(bytes_to_read)
+    }
+}
+
+impl<'v, 'a, S: DataSourceSource> Read for VdfsCompressedFile<'v, 'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let readed = self
+            .read_at_position(buf)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        self.position += readed as u64;
+        Ok(readed)
+    }
+}
+
+impl<'v, 'a, S: DataSourceSource> Seek for VdfsCompressedFile<'v, 'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+pub fn decompress_chunk(
+    compression: &VdfsFileCompression,
+    chunk_buffer: &[u8],
+    chunk_size: usize,
+) -> Result<Vec<u8>, VdfsError> {
+    let mut decoded_buffer = Vec::<u8>::new();
+    match compression {
+        VdfsFileCompression::Zlib => {
+            let mut decoder =
+                zlib::Decoder::new(chunk_buffer).map_err(|_| VdfsError::DecompressionError)?;
+            decoder
+                .read_to_end(&mut decoded_buffer)
+                .map_err(|_| VdfsError::DecompressionError)?;
+        }
+        VdfsFileCompression::Gzip => {
+            let mut decoder =
+                gzip::Decoder::new(chunk_buffer).map_err(|_| VdfsError::DecompressionError)?;
+            decoder
+                .read_to_end(&mut decoded_buffer)
+                .map_err(|_| VdfsError::DecompressionError)?;
+        }
+        VdfsFileCompression::Lzo => {
+            decoded_buffer = lzo::decompress(chunk_buffer, chunk_size)
+                .map_err(|_| VdfsError::DecompressionError)?;
+        }
+    }
+    Ok(decoded_buffer)
+}
+
+pub fn calc_hash(auth_type: VdfsFileAuth, data: &[u8]) -> Vec<u8> {
+    match auth_type {
+        VdfsFileAuth::Md5 => Md5::digest(data).to_vec(),
+        VdfsFileAuth::Sha1 => Sha1::digest(data).to_vec(),
+        VdfsFileAuth::Sha256 => Sha256::digest(data).to_vec(),
+    }
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    pub fn open_compressed_file(
+        &self,
+        path: &str,
+    ) -> Result<VdfsCompressedFile<'_, 'a, S>, VdfsError> {
+        VdfsCompressedFile::new(self.open_file(path)?)
+    }
+
+    pub fn open_compressed_file_by_record(
+        &self,
+        object_id: u64,
+        file_record: Box<Vdfs4CatalogFileRecord>,
+    ) -> Result<VdfsCompressedFile<'_, 'a, S>, VdfsError> {
+        VdfsCompressedFile::new(VdfsFile::new(self, object_id, file_record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+    use std::io::Write;
+
+    const LOG_CHUNK_SIZE: u32 = 12;
+
+    fn test_content() -> Vec<u8> {
+        (0..10000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Zlib chunks, the last chunk is stored uncompressed, then the extents table and
+    /// the descriptor of the layout 6.
+    fn compressed_file(image: &TestImage, content: &[u8], crc: u32) -> Vec<u8> {
+        let chunks: Vec<&[u8]> = content.chunks(1 << LOG_CHUNK_SIZE).collect();
+        let mut raw_file = Vec::new();
+        let mut extents = Vec::new();
+        for (chunk_index, chunk) in chunks.iter().enumerate() {
+            let (stored_chunk, flags) = if chunk_index + 1 == chunks.len() {
+                (chunk.to_vec(), VDFS4_CHUNK_FLAG_UNCOMPR)
+            } else {
+                let mut encoder = zlib::Encoder::new(Vec::new()).unwrap();
+                encoder.write_all(chunk).unwrap();
+                (encoder.finish().into_result().unwrap(), 0)
+            };
+            extents.push(Vdfs4CompressedExtent {
+                magic: *b"XT",
+                flags,
+                len_bytes: stored_chunk.len() as u32,
+                start: raw_file.len() as u64,
+            });
+            raw_file.extend(stored_chunk);
+        }
+        for extent in &extents {
+            raw_file.extend(image.encode(extent));
+        }
+        raw_file.extend(image.encode(&Vdfs4CompressedFileDescr {
+            reserved: [0; 7],
+            sign_type: VdfsFileSignatureType::None as u8,
+            magic: *b"CZip",
+            extents_num: extents.len() as u16,
+            layout_version: VDFS4_COMPR_LAYOUT_VER_06,
+            unpacked_size: content.len() as u64,
+            crc,
+            log_chunk_size: LOG_CHUNK_SIZE,
+            aes_nonce: [0; VDFS4_AES_NONCE_SIZE],
+        }));
+        raw_file
+    }
+
+    fn compressed_file_image(content: &[u8], crc: u32) -> TestDataSource {
+        let mut image = TestImage::new();
+        let raw_file = compressed_file(&image, content, crc);
+        image.add_regular_file(1, "lib.so", 20, &raw_file);
+        image.build()
+    }
+
+    #[test]
+    fn read_compressed_file() {
+        let content = test_content();
+        let data_source = compressed_file_image(&content, vdfs_crc::crc32(&content));
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let mut compressed_file = vdfs.open_compressed_file("/lib.so").unwrap();
+        assert_eq!(compressed_file.len(), content.len() as u64);
+        assert_eq!(compressed_file.get_chunks_count(), 3);
+
+        let mut unpacked = Vec::new();
+        compressed_file.read_to_end(&mut unpacked).unwrap();
+        assert!(unpacked == content);
+        assert_eq!(compressed_file.read_chunk(1).unwrap(), content[4096..8192]);
+        assert!(matches!(
+            compressed_file.read_chunk(3),
+            Err(VdfsError::CompressedFileChunkNotFound(3))
+        ));
+    }
+
+    #[test]
+    fn seek_compressed_file() {
+        let content = test_content();
+        let data_source = compressed_file_image(&content, vdfs_crc::crc32(&content));
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let mut compressed_file = vdfs.open_compressed_file("/lib.so").unwrap();
+
+        // Across the border of the first two chunks
+        let mut buffer = [0u8; 100];
+        compressed_file.seek(SeekFrom::Start(4050)).unwrap();
+        compressed_file.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, content[4050..4150]);
+
+        compressed_file.seek(SeekFrom::End(-8)).unwrap();
+        let mut tail = Vec::new();
+        compressed_file.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, content[content.len() - 8..]);
+
+        compressed_file.seek(SeekFrom::Current(100)).unwrap();
+        assert_eq!(compressed_file.read(&mut buffer).unwrap(), 0);
+        assert!(compressed_file.seek(SeekFrom::Current(-20000)).is_err());
+    }
+
+    #[test]
+    fn read_layout_5_descriptor() {
+        let content = b"short file in a single uncompressed chunk";
+        let mut image = TestImage::new();
+        let mut raw_file = content.to_vec();
+        raw_file.extend(image.encode(&Vdfs4CompressedExtent {
+            magic: *b"XT",
+            flags: VDFS4_CHUNK_FLAG_UNCOMPR,
+            len_bytes: content.len() as u32,
+            start: 0,
+        }));
+        raw_file.extend(image.encode(&Vdfs4CompressedFileDescrLayout5 {
+            magic: *b"CGzp",
+            extents_num: 1,
+            layout_version: VDFS4_COMPR_LAYOUT_VER_05,
+            unpacked_size: content.len() as u64,
+            crc: vdfs_crc::crc32(content),
+            log_chunk_size: LOG_CHUNK_SIZE,
+            aes_nonce: [0; VDFS4_AES_NONCE_SIZE],
+        }));
+        image.add_regular_file(1, "old", 20, &raw_file);
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        let mut compressed_file = vdfs.open_compressed_file("/old").unwrap();
+        assert_eq!(
+            compressed_file.get_descriptor_size(),
+            size_of::<Vdfs4CompressedFileDescrLayout5>() as u64
+        );
+        assert!(matches!(
+            compressed_file.get_descriptor().get_signature_type(),
+            Some(VdfsFileSignatureType::None)
+        ));
+        let mut unpacked = Vec::new();
+        compressed_file.read_to_end(&mut unpacked).unwrap();
+        assert_eq!(unpacked, content);
+    }
+
+    #[test]
+    fn unsupported_layout_version() {
+        let content = test_content();
+        let mut image = TestImage::new();
+        let mut raw_file = compressed_file(&image, &content, 0);
+        // layout_version is followed by unpacked_size, crc, log_chunk_size and aes_nonce
+        let version_position = raw_file.len() - 26;
+        raw_file[version_position] = 7;
+        image.add_regular_file(1, "lib.so", 20, &raw_file);
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert!(matches!(
+            vdfs.open_compressed_file("/lib.so"),
+            Err(VdfsError::UnsupportedCompressedFileLayout(7))
+        ));
+    }
+
+    #[test]
+    fn extent_with_wrong_magic() {
+        let content = test_content();
+        let mut image = TestImage::new();
+        let mut raw_file = compressed_file(&image, &content, 0);
+        let descriptor_size = size_of::<Vdfs4CompressedFileDescr>();
+        let first_extent_position =
+            raw_file.len() - descriptor_size - 3 * size_of::<Vdfs4CompressedExtent>();
+        raw_file[first_extent_position] = b'x';
+        image.add_regular_file(1, "lib.so", 20, &raw_file);
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert!(matches!(
+            vdfs.open_compressed_file("/lib.so"),
+            Err(VdfsError::CompressedFileExtentWrongSignature)
+        ));
+    }
+
+    #[test]
+    fn verify_content_crc() {
+        let content = test_content();
+        let crc = vdfs_crc::crc32(&content);
+        let data_source = compressed_file_image(&content, crc);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let compressed_file = vdfs.open_compressed_file("/lib.so").unwrap();
+        assert_eq!(
+            compressed_file.verify_content().unwrap(),
+            ContentVerdict::Ok
+        );
+        assert_eq!(
+            compressed_file.check_content(9999, crc),
+            ContentVerdict::SizeMismatch(10000, 9999)
+        );
+
+        let data_source = compressed_file_image(&content, crc ^ 1);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let compressed_file = vdfs.open_compressed_file("/lib.so").unwrap();
+        assert_eq!(
+            compressed_file.verify_content().unwrap(),
+            ContentVerdict::CrcMismatch(crc ^ 1, crc)
+        );
+    }
+}
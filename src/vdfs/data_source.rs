@@ -1,5 +1,6 @@
 use std::{
     cell::RefCell,
+    fs::File,
     io::{self, Read, Seek, Write},
 };
 
@@ -10,6 +11,10 @@ use bincode::{
 
 pub trait DataSourceSource: Read + Write + Seek {}
 
+impl DataSourceSource for File {}
+
+impl DataSourceSource for io::Cursor<Vec<u8>> {}
+
 #[derive(Debug)]
 pub struct DataSource<S: DataSourceSource> {
     source: RefCell<S>,
@@ -54,7 +59,7 @@ const BINCODE_CONFIG: Configuration<LittleEndian, Fixint, SkipFixedArrayLength,
         .with_fixed_int_encoding()
         .with_little_endian();
 
-impl<'a, W: Encode + Sized, S: DataSourceSource> WriteData<W> for DataSource<S> {
+impl<W: Encode + Sized, S: DataSourceSource> WriteData<W> for DataSource<S> {
     fn serialize(&self, data: &W) -> Result<Vec<u8>, DataSourceError> {
         bincode::encode_to_vec(data, BINCODE_CONFIG).map_err(|_| DataSourceError::SerializeError)
     }
@@ -71,15 +76,14 @@ impl<'a, W: Encode + Sized, S: DataSourceSource> WriteData<W> for DataSource<S>
     }
 }
 
-impl<'a, R: Decode + Sized, S: DataSourceSource> ReadData<R> for DataSource<S> {
+impl<R: Decode + Sized, S: DataSourceSource> ReadData<R> for DataSource<S> {
     fn deserialize(&self, data: &[u8]) -> Result<R, DataSourceError> {
-        bincode::decode_from_slice(&data, BINCODE_CONFIG)
+        bincode::decode_from_slice(data, BINCODE_CONFIG)
             .map_err(|_| DataSourceError::DeserializeError)
             .map(|(result, _)| result)
     }
 
     fn read_at(&self, position: u64) -> Result<DataPointer<R>, DataSourceError> {
-        //println!("DEBUG: {}", position);
         self.source
             .borrow_mut()
             .seek(io::SeekFrom::Start(position))
@@ -90,7 +94,7 @@ impl<'a, R: Decode + Sized, S: DataSourceSource> ReadData<R> for DataSource<S> {
     }
 }
 
-impl<'a, S: DataSourceSource> ReadBytes for DataSource<S> {
+impl<S: DataSourceSource> ReadBytes for DataSource<S> {
     fn read_bytes_at(&self, position: u64, size: u64) -> Result<Vec<u8>, DataSourceError> {
         self.source
             .borrow_mut()
@@ -105,7 +109,7 @@ impl<'a, S: DataSourceSource> ReadBytes for DataSource<S> {
     }
 }
 
-impl<'a, S: DataSourceSource> WriteBytes for DataSource<S> {
+impl<S: DataSourceSource> WriteBytes for DataSource<S> {
     fn write_bytes_at(&self, data: &[u8], position: u64) -> Result<u64, DataSourceError> {
         self.source
             .borrow_mut()
@@ -0,0 +1,325 @@
+use std::{
+    ffi::OsString,
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::ffi::OsStringExt,
+    path::PathBuf,
+};
+
+use super::{lookup::*, *};
+
+/// Random access to the data fork of a file inside the image.
+/// Compressed and encrypted files are read as they are stored.
+pub struct VdfsFile<'v, 'a, S: DataSourceSource> {
+    vdfs: &'v Vdfs<'a, S>,
+    object_id: u64,
+    file_record: Box<Vdfs4CatalogFileRecord>,
+    position: u64,
+}
+
+impl<'v, 'a, S: DataSourceSource> VdfsFile<'v, 'a, S> {
+    pub fn new(
+        vdfs: &'v Vdfs<'a, S>,
+        object_id: u64,
+        file_record: Box<Vdfs4CatalogFileRecord>,
+    ) -> Self {
+        Self {
+            vdfs,
+            object_id,
+            file_record,
+            position: 0,
+        }
+    }
+
+    pub fn get_vdfs(&self) -> &'v Vdfs<'a, S> {
+        self.vdfs
+    }
+
+    pub fn get_object_id(&self) -> u64 {
+        self.object_id
+    }
+
+    pub fn get_data_source(&self) -> &'a DataSource<S> {
+        self.vdfs.data_source
+    }
+
+    pub fn get_file_record(&self) -> &Vdfs4CatalogFileRecord {
+        &self.file_record
+    }
+
+    pub fn len(&self) -> u64 {
+        self.file_record.data_fork.size_in_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads exactly `size` bytes of the data fork starting from `position`.
+    pub fn read_bytes_at(&self, position: u64, size: u64) -> Result<Vec<u8>, VdfsError> {
+        let mut buf = vec![0u8; size as usize];
+        let mut readed = 0;
+        while readed < buf.len() {
+            let readed_at_position = self.read_at(position + readed as u64, &mut buf[readed..])?;
+            if readed_at_position == 0 {
+                return Err(VdfsError::DataSourceError(DataSourceError::ReadDataError));
+            }
+            readed += readed_at_position;
+        }
+        Ok(buf)
+    }
+
+    fn read_at(&self, position: u64, buf: &mut [u8]) -> Result<usize, VdfsError> {
+        let size = self.len();
+        if position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.vdfs.block_size;
+        let iblock = position / block_size;
+        let offset_in_block = position % block_size;
+        let extent = self
+            .vdfs
+            .get_file_iblock_extent(self.object_id, &self.file_record, iblock)?;
+        let Some((block, blocks_count)) = extent else {
+            // Sparse holes are not stored and read as zeros
+            let bytes_to_fill = (buf.len() as u64)
+                .min(block_size - offset_in_block)
+                .min(size - position) as usize;
+            buf[..bytes_to_fill].fill(0);
+            return Ok(bytes_to_fill);
+        };
+        let bytes_in_extent = self.vdfs.blocks_to_bytes(blocks_count) - offset_in_block;
+        let bytes_to_read = (buf.len() as u64).min(bytes_in_extent).min(size - position);
+
+        let data = self.vdfs.data_source.read_bytes_at(
+            self.vdfs.blocks_to_bytes(block) + offset_in_block,
+            bytes_to_read,
+        )?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl<'v, 'a, S: DataSourceSource> Read for VdfsFile<'v, 'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let readed = self
+            .read_at(self.position, buf)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        self.position += readed as u64;
+        Ok(readed)
+    }
+}
+
+impl<'v, 'a, S: DataSourceSource> Seek for VdfsFile<'v, 'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    pub fn open_file(&self, path: &str) -> Result<VdfsFile<'_, 'a, S>, VdfsError> {
+        let entry = self.lookup(path)?;
+        match entry.record {
+            CatalogRecord::File(file_record) => {
+                Ok(VdfsFile::new(self, entry.object_id, file_record))
+            }
+            CatalogRecord::Folder(_) => Err(VdfsError::NotAFile(String::from(path))),
+        }
+    }
+
+    /// Symbolic link target is stored in the data fork, optionally terminated by zero.
+    pub fn read_link_target(
+        &self,
+        file_object_id: u64,
+        catalog_file_record: &Vdfs4CatalogFileRecord,
+    ) -> Result<PathBuf, VdfsError> {
+        let link_file = VdfsFile::new(self, file_object_id, Box::new(catalog_file_record.clone()));
+        let mut target = link_file.read_bytes_at(0, link_file.len())?;
+        while target.last() == Some(&0) {
+            target.pop();
+        }
+        Ok(PathBuf::from(OsString::from_vec(target)))
+    }
+
+    pub fn read_link(&self, path: &str) -> Result<PathBuf, VdfsError> {
+        let entry = self.lookup(path)?;
+        match entry.record {
+            CatalogRecord::File(file_record)
+                if file_record.common.is_file_type(FileType::SymbolicLink) =>
+            {
+                self.read_link_target(entry.object_id, &file_record)
+            }
+            _ => Err(VdfsError::NotASymbolicLink(String::from(path))),
+        }
+    }
+
+    /// Returns the first block and the count of contiguous blocks that store the file logical block,
+    /// None if the block is in a sparse hole.
+    pub fn get_file_iblock_extent(
+        &self,
+        file_object_id: u64,
+        catalog_file_record: &Vdfs4CatalogFileRecord,
+        iblock: u64,
+    ) -> Result<Option<(u64, u64)>, VdfsError> {
+        if iblock >= catalog_file_record.data_fork.total_blocks_count {
+            return Err(VdfsError::FileBlockNotFound(iblock));
+        }
+        for extent in &catalog_file_record.data_fork.extents {
+            if extent.iblock <= iblock && extent.iblock + extent.extent.length > iblock {
+                let offset_in_extent = iblock - extent.iblock;
+                return Ok(Some((
+                    extent.extent.begin + offset_in_extent,
+                    extent.extent.length - offset_in_extent,
+                )));
+            }
+        }
+
+        let extent = self
+            .get_extent_tree()?
+            .find_extent(file_object_id, iblock)?;
+        Ok(extent.map(|extent| {
+            let offset_in_extent = iblock - extent.iblock;
+            (
+                extent.extent.begin + offset_in_extent,
+                extent.extent.length - offset_in_extent,
+            )
+        }))
+    }
+
+    pub fn get_file_iblock_position(
+        &self,
+        file_object_id: u64,
+        catalog_file_record: &Vdfs4CatalogFileRecord,
+        iblock: u64,
+    ) -> Result<Option<u64>, VdfsError> {
+        Ok(self
+            .get_file_iblock_extent(file_object_id, catalog_file_record, iblock)?
+            .map(|(block, _)| block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    const SIZE: u64 = 3 * BLOCK_SIZE + 100;
+
+    /// Block 0 is in the fork, blocks 1 and 2 are a hole, block 3 is in the extent tree.
+    fn sparse_file_image() -> TestDataSource {
+        let mut image = TestImage::new();
+        let mut record = file_record(FileType::Regular, SIZE);
+        record.data_fork.extents[0] = image.add_data(0, &[b'a'; BLOCK_SIZE as usize]);
+        let tail_extent = image.add_data(3, &[b'd'; 100]);
+        image
+            .add_extent(20, &tail_extent)
+            .add_file(1, "sparse", 20, record);
+        image.build()
+    }
+
+    fn read_exact_at(
+        file: &mut VdfsFile<impl DataSourceSource>,
+        position: u64,
+        len: usize,
+    ) -> Vec<u8> {
+        let mut buf = vec![0xff; len];
+        file.seek(SeekFrom::Start(position)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn read_sparse_file() {
+        let data_source = sparse_file_image();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let mut file = vdfs.open_file("/sparse").unwrap();
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        let mut expected = vec![b'a'; BLOCK_SIZE as usize];
+        expected.resize(3 * BLOCK_SIZE as usize, 0);
+        expected.extend_from_slice(&[b'd'; 100]);
+        assert_eq!(content, expected);
+
+        // Across the start and the end of the hole
+        assert_eq!(
+            read_exact_at(&mut file, BLOCK_SIZE - 2, 4),
+            [b'a', b'a', 0, 0]
+        );
+        assert_eq!(
+            read_exact_at(&mut file, 3 * BLOCK_SIZE - 2, 4),
+            [0, 0, b'd', b'd']
+        );
+    }
+
+    #[test]
+    fn read_at_end_of_file() {
+        let data_source = sparse_file_image();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let mut file = vdfs.open_file("/sparse").unwrap();
+        let mut buf = [0u8; 16];
+
+        assert_eq!(file.seek(SeekFrom::End(-4)).unwrap(), SIZE - 4);
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+        assert_eq!(file.seek(SeekFrom::Current(100)).unwrap(), SIZE + 100);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        assert!(file.seek(SeekFrom::Current(-(SIZE as i64) - 101)).is_err());
+    }
+
+    #[test]
+    fn block_past_the_fork_is_not_found() {
+        let data_source = sparse_file_image();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let file = vdfs.open_file("/sparse").unwrap();
+        let record = file.get_file_record();
+
+        assert_eq!(
+            vdfs.get_file_iblock_position(20, record, 0).unwrap(),
+            Some(DATA_BEGIN)
+        );
+        assert_eq!(vdfs.get_file_iblock_position(20, record, 2).unwrap(), None);
+        assert_eq!(
+            vdfs.get_file_iblock_position(20, record, 3).unwrap(),
+            Some(DATA_BEGIN + 1)
+        );
+        assert!(matches!(
+            vdfs.get_file_iblock_position(20, record, 4),
+            Err(VdfsError::FileBlockNotFound(4))
+        ));
+    }
+
+    #[test]
+    fn read_symlink_target() {
+        let mut image = TestImage::new();
+        image
+            .add_symlink(1, "lib", 20, "usr/lib")
+            .add_symlink(1, "sh", 21, "/bin/busybox\0")
+            .add_regular_file(1, "file", 22, b"usr/lib");
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert_eq!(vdfs.read_link("/lib").unwrap(), PathBuf::from("usr/lib"));
+        assert_eq!(
+            vdfs.read_link("/sh").unwrap(),
+            PathBuf::from("/bin/busybox")
+        );
+        assert!(matches!(
+            vdfs.read_link("/file"),
+            Err(VdfsError::NotASymbolicLink(_))
+        ));
+        assert!(matches!(
+            vdfs.read_link("/"),
+            Err(VdfsError::NotASymbolicLink(_))
+        ));
+    }
+}
@@ -1,10 +1,15 @@
+use std::{
+    cmp::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use consts::*;
 
 pub mod consts;
 
 pub trait HasCrc32 {
     fn get_crc32(&self) -> u32;
-    fn get_body_without_crc32<'a>(&'a self, bytes: &'a [u8]) -> &[u8] {
+    fn get_body_without_crc32<'a>(&'a self, bytes: &'a [u8]) -> &'a [u8] {
         let length_without_crc32 = bytes.len() - 4;
         &bytes[..length_without_crc32]
     }
@@ -15,9 +20,8 @@ pub trait HasSignature {
     fn check_signature(&self, string: &str) -> bool {
         let signature = self.get_signature();
         if signature.len() == string.len() {
-            for (i, c) in string.chars().into_iter().enumerate() {
+            for (i, c) in string.chars().enumerate() {
                 if !c.eq(&(signature[i] as char)) {
-                    //println!("Signature mismatch: string: {} signature {} != char {} at index {}", string, signature[i] as char, string.as_bytes()[i] as char,i);
                     return false;
                 }
             }
@@ -32,6 +36,11 @@ pub trait VdfsBtreeKey: PartialEq + PartialOrd + bincode::Encode + bincode::Deco
     fn get_value_offset(&self) -> u64 {
         self.get_generic_key().key_len as u64
     }
+
+    /// Order of the keys in the tree, names are compared case-insensitively on such volumes.
+    fn compare(&self, other: &Self, _case_insensitive: bool) -> Option<Ordering> {
+        self.partial_cmp(other)
+    }
 }
 
 pub trait VdfsBtreeNode: HasSignature + bincode::Encode + bincode::Decode + HasVersion {
@@ -155,21 +164,21 @@ pub struct Vdfs4BaseTable {
     pub translation_table_offsets: [u64; VDFS4_SF_NR as usize],
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone, Copy)]
 pub struct Vdfs4BaseTableRecord {
     pub meta_iblock: u64,
     pub sync_count: u32,
     pub mount_count: u32,
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone, Copy)]
 pub struct Vdfs4ExtendedRecord {
     pub object_id: u64,
     pub table_index: u64,
     pub meta_iblock: u64,
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone, Copy)]
 pub struct Vdfs4ExtendedTable {
     pub descriptor: Vdfs4SnapshotDescriptor,
     pub records_count: u32,
@@ -213,7 +222,7 @@ pub struct Vdfs4XattrTreeKey {
     pub name: [u8; VDFS4_XATTR_NAME_MAX_LEN],
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone)]
 pub struct Vdfs4CatalogFolderRecord {
     pub flags: u32,
     pub generation: u32,
@@ -232,13 +241,13 @@ pub struct Vdfs4CatalogFolderRecord {
     pub access_time: Vdfs4Timespec,
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone)]
 pub struct Vdfs4CatalogFileRecord {
     pub common: Vdfs4CatalogFolderRecord,
     pub data_fork: Vdfs4Fork,
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone)]
 pub struct Vdfs4Fork {
     /** The size in bytes of the valid data in the fork */
     pub size_in_bytes: u64,
@@ -251,7 +260,7 @@ pub struct Vdfs4Fork {
     pub extents: [Vdfs4Iextent; VDFS4_EXTENTS_COUNT_IN_FORK],
 }
 
-#[derive(bincode::Decode, bincode::Encode, Debug)]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone)]
 pub struct Vdfs4Iextent {
     /** file data location */
     pub extent: Vdfs4Extent,
@@ -285,6 +294,18 @@ pub struct GenericIndexValue {
     pub node_id: u32,
 }
 
+/// Compressed file descriptor of layout 0x0005, it is the tail of the newer descriptor.
+#[derive(bincode::Decode, bincode::Encode, Debug)]
+pub struct Vdfs4CompressedFileDescrLayout5 {
+    pub magic: [u8; 4],
+    pub extents_num: u16,
+    pub layout_version: u16,
+    pub unpacked_size: u64,
+    pub crc: u32,
+    pub log_chunk_size: u32,
+    pub aes_nonce: [u8; VDFS4_AES_NONCE_SIZE],
+}
+
 #[derive(bincode::Decode, bincode::Encode, Debug)]
 pub struct Vdfs4CompressedFileDescr {
     /* new fields are added before magic for easier backward compatibility */
@@ -308,6 +329,37 @@ pub struct Vdfs4CompressedExtent {
     pub start: u64,
 }
 
+impl Vdfs4SuperBlocks {
+    /// Redundant copies of the superblock, in the order they are tried.
+    pub fn get_super_block_copies(&self) -> [&Vdfs4SuperBlock; 2] {
+        [&self.sign1, &self.sign2]
+    }
+}
+
+impl Vdfs4SuperBlock {
+    pub fn get_layout_version(&self) -> Option<VdfsLayoutVersion> {
+        VdfsLayoutVersion::from_bytes(&self.layout_version)
+    }
+
+    pub fn get_layout_version_string(&self) -> String {
+        String::from_utf8_lossy(&self.layout_version).into_owned()
+    }
+
+    pub fn get_block_size(&self) -> u64 {
+        1 << self.log_block_size
+    }
+
+    pub fn get_super_page_size(&self) -> u64 {
+        1 << self.log_super_page_size
+    }
+
+    pub fn has_supported_geometry(&self) -> bool {
+        (VDFS4_MIN_LOG_BLOCK_SIZE..=VDFS4_MAX_LOG_BLOCK_SIZE).contains(&self.log_block_size)
+            && (self.log_block_size..=VDFS4_MAX_LOG_SUPER_PAGE_SIZE)
+                .contains(&self.log_super_page_size)
+    }
+}
+
 impl HasCrc32 for Vdfs4SuperBlock {
     fn get_crc32(&self) -> u32 {
         self.checksum
@@ -369,6 +421,26 @@ impl HasSignature for Vdfs4MetaHashtable {
     }
 }
 
+impl From<Vdfs4CompressedFileDescrLayout5> for Vdfs4CompressedFileDescr {
+    fn from(descriptor: Vdfs4CompressedFileDescrLayout5) -> Self {
+        let mut converted = Vdfs4CompressedFileDescr {
+            reserved: [0; 7],
+            sign_type: VdfsFileSignatureType::None as u8,
+            magic: descriptor.magic,
+            extents_num: descriptor.extents_num,
+            layout_version: descriptor.layout_version,
+            unpacked_size: descriptor.unpacked_size,
+            crc: descriptor.crc,
+            log_chunk_size: descriptor.log_chunk_size,
+            aes_nonce: descriptor.aes_nonce,
+        };
+        if converted.get_auth().is_some() {
+            converted.sign_type = VdfsFileSignatureType::Rsa1024 as u8;
+        }
+        converted
+    }
+}
+
 impl HasSignature for Vdfs4CompressedFileDescr {
     fn get_signature(&self) -> &[u8] {
         &self.magic
@@ -382,12 +454,14 @@ impl HasCrc32 for Vdfs4CompressedFileDescr {
 }
 
 impl Vdfs4CompressedFileDescr {
+    /// The first byte of the magic is replaced by the hash type for authenticated files.
     pub fn get_compression(&self) -> Option<VdfsFileCompression> {
-        if self.check_signature(VDFS4_COMPR_ZIP_FILE_DESCR_MAGIC) {
+        let magic = &self.magic[1..];
+        if magic == &VDFS4_COMPR_ZIP_FILE_DESCR_MAGIC.as_bytes()[1..] {
             return Some(VdfsFileCompression::Zlib);
-        } else if self.check_signature(VDFS4_COMPR_GZIP_FILE_DESCR_MAGIC) {
+        } else if magic == &VDFS4_COMPR_GZIP_FILE_DESCR_MAGIC.as_bytes()[1..] {
             return Some(VdfsFileCompression::Gzip);
-        } else if self.check_signature(VDFS4_COMPR_LZO_FILE_DESCR_MAGIC) {
+        } else if magic == &VDFS4_COMPR_LZO_FILE_DESCR_MAGIC.as_bytes()[1..] {
             return Some(VdfsFileCompression::Lzo);
         }
         None
@@ -452,6 +526,10 @@ impl VdfsBtreeKey for Vdfs4CatTreeKey {
     fn get_generic_key(&self) -> &Vdfs4GenericKey {
         &self.gen_key
     }
+
+    fn compare(&self, other: &Self, case_insensitive: bool) -> Option<Ordering> {
+        self.compare_with_collation(other, case_insensitive)
+    }
 }
 
 impl PartialEq for Vdfs4CatTreeKey {
@@ -467,15 +545,23 @@ impl PartialEq for Vdfs4CatTreeKey {
 
 impl PartialOrd for Vdfs4CatTreeKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.compare_with_collation(other, false)
+    }
+}
+
+impl Vdfs4CatTreeKey {
+    fn compare_with_collation(
+        &self,
+        other: &Self,
+        case_insensitive: bool,
+    ) -> Option<std::cmp::Ordering> {
         match self.parent_id.partial_cmp(&other.parent_id) {
             Some(core::cmp::Ordering::Equal) => {}
             ord => return ord,
         }
-        match self.name[..(self.name_len as usize)]
-            .partial_cmp(&other.name[..(other.name_len as usize)])
-        {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
+        match compare_names(self.get_name(), other.get_name(), case_insensitive) {
+            core::cmp::Ordering::Equal => {}
+            ord => return Some(ord),
         }
         match self.name_len.partial_cmp(&other.name_len) {
             Some(core::cmp::Ordering::Equal) => {}
@@ -489,6 +575,39 @@ impl PartialOrd for Vdfs4CatTreeKey {
     }
 }
 
+/// Case-insensitive volumes fold ASCII letters only, like the kernel driver does.
+pub fn compare_names(first: &[u8], second: &[u8], case_insensitive: bool) -> Ordering {
+    if case_insensitive {
+        let first = first.iter().map(u8::to_ascii_lowercase);
+        let second = second.iter().map(u8::to_ascii_lowercase);
+        first.cmp(second)
+    } else {
+        first.cmp(second)
+    }
+}
+
+impl VdfsBtreeKey for Vdfs4XattrTreeKey {
+    fn get_generic_key(&self) -> &Vdfs4GenericKey {
+        &self.gen_key
+    }
+}
+
+impl PartialEq for Vdfs4XattrTreeKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_id == other.object_id && self.get_name() == other.get_name()
+    }
+}
+
+impl PartialOrd for Vdfs4XattrTreeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self.object_id.partial_cmp(&other.object_id) {
+            Some(core::cmp::Ordering::Equal) => {}
+            ord => return ord,
+        }
+        self.get_name().partial_cmp(other.get_name())
+    }
+}
+
 impl VdfsBtreeKey for Vdfs4ExtTreeKey {
     fn get_generic_key(&self) -> &Vdfs4GenericKey {
         &self.gen_key
@@ -511,6 +630,12 @@ impl PartialOrd for Vdfs4ExtTreeKey {
     }
 }
 
+impl Default for Vdfs4GenericKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Vdfs4GenericKey {
     pub fn new() -> Self {
         Self {
@@ -531,6 +656,30 @@ impl Vdfs4CatTreeKey {
         CatalogTreeRecordType::from_u8(self.record_type).unwrap()
     }
 
+    /// Search key for the record `name` inside the folder `parent_id`.
+    /// Name must not be longer than VDFS4_FILE_NAME_LEN bytes.
+    pub fn from_parent_and_name(parent_id: u64, name: &[u8]) -> Self {
+        let mut key_name = [0u8; VDFS4_FILE_NAME_LEN];
+        key_name[..name.len()].copy_from_slice(name);
+        Self {
+            gen_key: Vdfs4GenericKey::new(),
+            parent_id,
+            object_id: 0,
+            record_type: 0,
+            name_len: name.len() as u8,
+            name: key_name,
+        }
+    }
+
+    pub fn get_name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    pub fn has_parent_and_name(&self, parent_id: u64, name: &[u8], case_insensitive: bool) -> bool {
+        self.parent_id == parent_id
+            && compare_names(self.get_name(), name, case_insensitive) == Ordering::Equal
+    }
+
     pub fn child_of_root() -> Self {
         Self {
             gen_key: Vdfs4GenericKey::new(),
@@ -543,16 +692,56 @@ impl Vdfs4CatTreeKey {
     }
 }
 
+impl Vdfs4XattrTreeKey {
+    /// Name must not be longer than VDFS4_XATTR_NAME_MAX_LEN bytes.
+    pub fn from_object_id_and_name(object_id: u64, name: &[u8]) -> Self {
+        let mut key_name = [0u8; VDFS4_XATTR_NAME_MAX_LEN];
+        key_name[..name.len()].copy_from_slice(name);
+        Self {
+            gen_key: Vdfs4GenericKey::new(),
+            object_id,
+            name_len: name.len() as u8,
+            name: key_name,
+        }
+    }
+
+    pub fn get_name(&self) -> &[u8] {
+        &self.name[..(self.name_len as usize).min(VDFS4_XATTR_NAME_MAX_LEN)]
+    }
+
+    pub fn get_name_string(&self) -> String {
+        String::from_utf8_lossy(self.get_name()).into_owned()
+    }
+}
+
 impl Vdfs4ExtTreeKey {
     pub fn from_object_id(object_id: u64) -> Self {
+        Self::from_object_id_and_iblock(object_id, 0)
+    }
+
+    pub fn from_object_id_and_iblock(object_id: u64, iblock: u64) -> Self {
         Self {
             gen_key: Vdfs4GenericKey::new(),
             object_id,
-            iblock: 0,
+            iblock,
         }
     }
 }
 
+impl Vdfs4Timespec {
+    /// Seconds since the epoch, `seconds_high` holds the bits above 32 for post-2038 dates.
+    pub fn get_seconds(&self) -> u64 {
+        ((self.seconds_high as u64) << 32) | self.seconds as u64
+    }
+
+    /// None when the time cannot be represented by the platform.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        Duration::from_secs(self.get_seconds())
+            .checked_add(Duration::from_nanos(self.nanoseconds as u64))
+            .and_then(|duration| UNIX_EPOCH.checked_add(duration))
+    }
+}
+
 impl HasVersion for Vdfs4BaseTableRecord {
     fn get_version(&self) -> u64 {
         ((self.mount_count as u64) << 32) + self.sync_count as u64
@@ -586,6 +775,13 @@ impl Vdfs4CatalogFolderRecord {
         FileType::from_u16(self.file_mode)
     }
 
+    /// Device nodes store the device number in place of total_items_count.
+    pub fn get_device_number(&self) -> (u32, u32) {
+        let major = self.total_items_count >> KERNEL_MINOR_BITS;
+        let minor = self.total_items_count & ((1 << KERNEL_MINOR_BITS) - 1);
+        (major as u32, minor as u32)
+    }
+
     pub fn has_file_flag(&self, flag: VdfsFileFlags) -> bool {
         if let VdfsFileFlags::HardLink = flag {
             if self.links_count > 1 {
@@ -595,3 +791,102 @@ impl Vdfs4CatalogFolderRecord {
         self.flags & (1 << (flag as u32)) != 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timespec(seconds: u32, seconds_high: u32, nanoseconds: u32) -> Vdfs4Timespec {
+        Vdfs4Timespec {
+            seconds,
+            seconds_high,
+            nanoseconds,
+        }
+    }
+
+    #[test]
+    fn timespec_after_2038() {
+        let time = timespec(5, 1, 7).to_system_time().unwrap();
+        assert_eq!(
+            time.duration_since(UNIX_EPOCH).unwrap(),
+            Duration::new((1 << 32) + 5, 7)
+        );
+    }
+
+    #[test]
+    fn timespec_out_of_range() {
+        assert_eq!(timespec(u32::MAX, u32::MAX, 0).to_system_time(), None);
+        assert_eq!(
+            timespec(u32::MAX, u32::MAX, u32::MAX).to_system_time(),
+            None
+        );
+    }
+
+    fn catalog_key(parent_id: u64, name: &[u8], object_id: u64) -> Vdfs4CatTreeKey {
+        Vdfs4CatTreeKey {
+            object_id,
+            ..Vdfs4CatTreeKey::from_parent_and_name(parent_id, name)
+        }
+    }
+
+    #[test]
+    fn names_with_mixed_case() {
+        assert_eq!(compare_names(b"Abc", b"abc", false), Ordering::Less);
+        assert_eq!(compare_names(b"Abc", b"abc", true), Ordering::Equal);
+        // 'Z' sorts before 'a' by bytes, but after it with folded case
+        assert_eq!(compare_names(b"Zeta", b"alpha", false), Ordering::Less);
+        assert_eq!(compare_names(b"Zeta", b"alpha", true), Ordering::Greater);
+        assert_eq!(compare_names(b"FILE.txt", b"file", true), Ordering::Greater);
+        // Only ASCII letters are folded
+        assert_eq!(
+            compare_names("É".as_bytes(), "é".as_bytes(), true),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn prefix_sorts_before_longer_name() {
+        for case_insensitive in [false, true] {
+            assert_eq!(
+                compare_names(b"file", b"file.txt", case_insensitive),
+                Ordering::Less
+            );
+            let short = catalog_key(1, b"file", 20);
+            let long = catalog_key(1, b"file.txt", 10);
+            assert_eq!(short.compare(&long, case_insensitive), Some(Ordering::Less));
+            assert_eq!(
+                long.compare(&short, case_insensitive),
+                Some(Ordering::Greater)
+            );
+        }
+    }
+
+    #[test]
+    fn parent_id_has_priority_over_name() {
+        let first = catalog_key(1, b"zzz", 30);
+        let second = catalog_key(2, b"aaa", 20);
+        assert_eq!(first.compare(&second, false), Some(Ordering::Less));
+        assert_eq!(second.compare(&first, true), Some(Ordering::Greater));
+    }
+
+    #[test]
+    fn object_id_breaks_ties_of_equal_names() {
+        let first = catalog_key(1, b"name", 10);
+        let second = catalog_key(1, b"name", 11);
+        assert_eq!(first.compare(&second, false), Some(Ordering::Less));
+        // Names equal with folded case are ordered by the object id too
+        let upper = catalog_key(1, b"NAME", 12);
+        assert_eq!(upper.compare(&first, true), Some(Ordering::Greater));
+        assert_eq!(upper.compare(&first, false), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn key_has_parent_and_name() {
+        let key = catalog_key(5, b"Name", 10);
+        assert!(key.has_parent_and_name(5, b"Name", false));
+        assert!(!key.has_parent_and_name(5, b"name", false));
+        assert!(key.has_parent_and_name(5, b"name", true));
+        assert!(!key.has_parent_and_name(6, b"Name", true));
+        assert!(!key.has_parent_and_name(5, b"Nam", true));
+    }
+}
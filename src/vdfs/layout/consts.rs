@@ -6,9 +6,9 @@ use super::{
 
 const fn max_u64(a: u64, b: u64) -> u64 {
     if a > b {
-        return a;
+        a
     } else {
-        return b;
+        b
     }
 }
 
@@ -32,6 +32,10 @@ pub const VDFS4_KEY_MAX_LEN: u64 = max_u64(
 
 pub const VDFS4_EXTENTS_COUNT_IN_FORK: usize = 9;
 
+pub const VDFS4_SB_SIGNATURE: &str = "VDFS";
+pub const VDFS4_LAYOUT_VERSION_2006: &[u8; 4] = b"2006";
+pub const VDFS4_LAYOUT_VERSION_2007: &[u8; 4] = b"2007";
+
 pub const VDFS4_SNAPSHOT_BASE_TABLE: &str = "CoWB";
 pub const VDFS4_SNAPSHOT_EXTENDED_TABLE: &str = "CoWE";
 
@@ -45,10 +49,18 @@ pub const VDFS4_MD5_AUTH: char = 'I';
 pub const VDFS4_SHA1_AUTH: char = 'H';
 pub const VDFS4_SHA256_AUTH: char = 'h';
 pub const VDFS4_COMPR_EXT_MAGIC: &str = "XT";
+/** descriptor without sign_type, the signature is always RSA1024 */
+pub const VDFS4_COMPR_LAYOUT_VER_05: u16 = 0x0005;
+pub const VDFS4_COMPR_LAYOUT_VER_06: u16 = 0x0006;
 
 pub const VDFS4_CHUNK_FLAG_UNCOMPR: u16 = 0x1;
 pub const VDFS4_CHUNK_FLAG_ENCRYPTED: u16 = 0x2;
 
+/** hash_type of the superblock */
+pub const VDFS4_HASH_SHA1: u8 = 1;
+pub const VDFS4_HASH_SHA256: u8 = 2;
+pub const VDFS4_HASH_MD5: u8 = 3;
+
 pub const VDFS4_MD5_HASH_LEN: usize = 16;
 pub const VDFS4_SHA1_HASH_LEN: usize = 20;
 pub const VDFS4_SHA256_HASH_LEN: usize = 32;
@@ -59,13 +71,13 @@ pub const VDFS4_MAX_CRYPTED_HASH_LEN: usize = 256;
 // const VDFS4_MIN_LOG_CHUNK_SIZE: usize = 12;
 // const VDFS4_MAX_LOG_CHUNK_SIZE: usize = 20;
 pub const VDFS4_AES_NONCE_SIZE: usize = 8;
-// const VDFS4_AES_KEY_LENGTH: usize = 16;
+pub const VDFS4_AES_KEY_LENGTH: usize = 16;
 // const VDFS4_AES_CHUNK_ALIGN_LEN: usize = 16;
 // const VDFS4_AES_CHUNK_ALIGN_START: usize = 16;
 
 pub const VDFS4_SNAPSHOT_EXT_SIZE: usize = 4096;
 pub const VDFS4_SNAPSHOT_EXT_TABLES: usize = 8;
-// const VDFS4_SNAPSHOT_BASE_TABLES: usize = 2;
+pub const VDFS4_SNAPSHOT_BASE_TABLES: u32 = 2;
 
 pub const VDFS4_SF_NR: u32 = VDFS4_LSFILE - VDFS4_FSFILE + 1;
 
@@ -135,18 +147,26 @@ pub enum VdfsFileFlags {
     ProfiledFile = 18,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VdfsLayoutVersion {
+    V2006,
+    V2007,
+}
+
 pub enum VdfsFileCompression {
     Zlib,
     Gzip,
     Lzo,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VdfsFileAuth {
     Md5,
     Sha1,
     Sha256,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VdfsFileSignatureType {
     None = 0x0,
     Rsa1024 = 0x1,
@@ -186,17 +206,17 @@ impl BtreeType {
 impl SpecialInodeIds {
     pub fn from_u32(index: u32) -> Option<SpecialInodeIds> {
         match index {
-            0 => return Some(SpecialInodeIds::RootDirObject),
-            1 => return Some(SpecialInodeIds::Root),
-            2 => return Some(SpecialInodeIds::CatTree),
-            3 => return Some(SpecialInodeIds::SpaceBitmap),
-            4 => return Some(SpecialInodeIds::ExtentsTree),
-            5 => return Some(SpecialInodeIds::FreeInodeBitmap),
-            6 => return Some(SpecialInodeIds::XattrTree),
-            7 => return Some(SpecialInodeIds::Snapshot),
-            8 => return Some(SpecialInodeIds::OrphanInodes),
-            9 => return Some(SpecialInodeIds::FirstFile),
-            _ => return None,
+            0 => Some(SpecialInodeIds::RootDirObject),
+            1 => Some(SpecialInodeIds::Root),
+            2 => Some(SpecialInodeIds::CatTree),
+            3 => Some(SpecialInodeIds::SpaceBitmap),
+            4 => Some(SpecialInodeIds::ExtentsTree),
+            5 => Some(SpecialInodeIds::FreeInodeBitmap),
+            6 => Some(SpecialInodeIds::XattrTree),
+            7 => Some(SpecialInodeIds::Snapshot),
+            8 => Some(SpecialInodeIds::OrphanInodes),
+            9 => Some(SpecialInodeIds::FirstFile),
+            _ => None,
         }
     }
 }
@@ -204,22 +224,33 @@ impl SpecialInodeIds {
 impl CatalogTreeRecordType {
     pub fn from_u8(index: u8) -> Option<CatalogTreeRecordType> {
         match index {
-            0 => return Some(Self::Dummy),
-            1 => return Some(Self::Folder),
-            2 => return Some(Self::File),
-            3 => return Some(Self::HLink),
-            5 => return Some(Self::ILink),
-            10 => return Some(Self::UnpackInode),
-            _ => return None,
+            0 => Some(Self::Dummy),
+            1 => Some(Self::Folder),
+            2 => Some(Self::File),
+            3 => Some(Self::HLink),
+            5 => Some(Self::ILink),
+            10 => Some(Self::UnpackInode),
+            _ => None,
         }
     }
 }
 
+#[deprecated(
+    note = "the block size is read from the superblock, use Vdfs4SuperBlock::get_block_size"
+)]
 pub const BLOCK_SIZE_DEFAULT: u64 = 4096;
+#[deprecated(
+    note = "the super page size is read from the superblock, use Vdfs4SuperBlock::get_super_page_size"
+)]
 pub const SUPER_PAGE_SIZE_DEFAULT: u64 = 16384;
 
+pub const VDFS4_MIN_LOG_BLOCK_SIZE: u8 = 9;
+pub const VDFS4_MAX_LOG_BLOCK_SIZE: u8 = 16;
+/** super page is the size of a B-tree node */
+pub const VDFS4_MAX_LOG_SUPER_PAGE_SIZE: u8 = 20;
+
 pub const fn size_ceil_to_block(size: usize, block_size: usize) -> usize {
-    ((size + block_size - 1) / block_size) * block_size
+    size.div_ceil(block_size) * block_size
 }
 
 impl VdfsFileSignatureType {
@@ -241,7 +272,26 @@ impl VdfsFileSignatureType {
     }
 }
 
+impl VdfsLayoutVersion {
+    pub fn from_bytes(value: &[u8; 4]) -> Option<Self> {
+        match value {
+            VDFS4_LAYOUT_VERSION_2006 => Some(Self::V2006),
+            VDFS4_LAYOUT_VERSION_2007 => Some(Self::V2007),
+            _ => None,
+        }
+    }
+}
+
 impl VdfsFileAuth {
+    pub fn from_hash_type(hash_type: u8) -> Option<Self> {
+        match hash_type {
+            VDFS4_HASH_SHA1 => Some(Self::Sha1),
+            VDFS4_HASH_SHA256 => Some(Self::Sha256),
+            VDFS4_HASH_MD5 => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
     pub fn get_hash_len(&self) -> u64 {
         match self {
             VdfsFileAuth::Md5 => VDFS4_MD5_HASH_LEN as u64,
@@ -257,6 +307,9 @@ impl VdfsFileAuth {
 
 pub const FILE_TYPE_MASK: u16 = 0o0170000; /* These bits determine file type.  */
 
+/* Device numbers are kept in the kernel format: major << 20 | minor.  */
+pub const KERNEL_MINOR_BITS: u64 = 20;
+
 #[derive(Debug, Clone, Copy)]
 pub enum FileType {
     Directory = 0o0040000,
@@ -296,4 +349,17 @@ impl FileType {
     pub fn is_file_type(&self, file_mode: u16) -> bool {
         (file_mode & FILE_TYPE_MASK) == self.to_u16()
     }
+
+    /// File type letter as in `ls -l` and mknod
+    pub fn get_type_char(&self) -> char {
+        match self {
+            FileType::Directory => 'd',
+            FileType::CharacterDevice => 'c',
+            FileType::BlockDevice => 'b',
+            FileType::Regular => '-',
+            FileType::Fifo => 'p',
+            FileType::SymbolicLink => 'l',
+            FileType::Socket => 's',
+        }
+    }
 }
@@ -0,0 +1,204 @@
+use super::*;
+
+#[derive(Debug)]
+pub enum CatalogRecord {
+    Folder(Vdfs4CatalogFolderRecord),
+    File(Box<Vdfs4CatalogFileRecord>),
+}
+
+#[derive(Debug)]
+pub struct CatalogEntry {
+    pub object_id: u64,
+    pub record_type: CatalogTreeRecordType,
+    pub record: CatalogRecord,
+}
+
+impl CatalogRecord {
+    pub fn get_common(&self) -> &Vdfs4CatalogFolderRecord {
+        match self {
+            CatalogRecord::Folder(folder_record) => folder_record,
+            CatalogRecord::File(file_record) => &file_record.common,
+        }
+    }
+}
+
+impl CatalogEntry {
+    pub fn is_folder(&self) -> bool {
+        matches!(self.record, CatalogRecord::Folder(_))
+    }
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// Resolves an absolute path inside the image, e.g. "/usr/bin/foo".
+    /// Hard links are resolved to the record of the linked inode.
+    pub fn lookup(&self, path: &str) -> Result<CatalogEntry, VdfsError> {
+        let catalog_tree = self.get_catalog_tree()?;
+        let root_record = catalog_tree
+            .root_record()?
+            .ok_or_else(|| VdfsError::PathNotFound(String::from("/")))?;
+        let mut entries = vec![self.get_catalog_entry(&root_record)?];
+
+        for component in path.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => {
+                    if entries.len() > 1 {
+                        entries.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let parent = entries.last().unwrap();
+            if !parent.is_folder() {
+                return Err(VdfsError::NotADirectory(String::from(path)));
+            }
+            if component.len() > VDFS4_FILE_NAME_LEN {
+                return Err(VdfsError::PathNotFound(String::from(path)));
+            }
+            let record = catalog_tree
+                .find_record(parent.object_id, component.as_bytes())?
+                .ok_or_else(|| VdfsError::PathNotFound(String::from(path)))?;
+            entries.push(self.get_catalog_entry(&record)?);
+        }
+
+        Ok(entries.pop().unwrap())
+    }
+
+    /// Decodes the value of a catalog record.
+    pub fn get_catalog_entry(
+        &self,
+        record: &DataPointer<Vdfs4CatTreeKey>,
+    ) -> Result<CatalogEntry, VdfsError> {
+        let record_type = record.data.get_record_type();
+        let catalog_record = match record_type {
+            CatalogTreeRecordType::Folder => {
+                CatalogRecord::Folder(record.get_record_value(self.data_source)?.data)
+            }
+            CatalogTreeRecordType::File => {
+                CatalogRecord::File(Box::new(record.get_record_value(self.data_source)?.data))
+            }
+            CatalogTreeRecordType::HLink => {
+                return self.get_hard_link_inode_entry(record.data.object_id)
+            }
+            _ => {
+                return Err(VdfsError::UnsupportedCatalogRecordType(
+                    record.data.record_type,
+                ))
+            }
+        };
+        Ok(CatalogEntry {
+            object_id: record.data.object_id,
+            record_type,
+            record: catalog_record,
+        })
+    }
+
+    /// Hard linked inodes are stored in the catalog as a nameless child of themselves.
+    pub fn get_hard_link_inode_entry(&self, object_id: u64) -> Result<CatalogEntry, VdfsError> {
+        let inode_record = self
+            .get_catalog_tree()?
+            .find_inode_record(object_id)?
+            .ok_or(VdfsError::HardLinkInodeNotFound(object_id))?;
+        if let CatalogTreeRecordType::HLink = inode_record.data.get_record_type() {
+            return Err(VdfsError::HardLinkInodeNotFound(object_id));
+        }
+        self.get_catalog_entry(&inode_record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    /// /usr/bin/foo, /usr/lib/* to spread /usr over several leaves, /README
+    fn usr_image(case_insensitive: bool) -> TestImage {
+        let mut image = TestImage::new();
+        image.super_block.case_insensitive = case_insensitive;
+        image
+            .add_folder(1, "usr", 10, folder_record())
+            .add_folder(10, "bin", 11, folder_record())
+            .add_folder(10, "lib", 12, folder_record())
+            .add_regular_file(11, "foo", 20, b"foo")
+            .add_regular_file(1, "README", 21, b"readme");
+        for index in 0..10 {
+            image.add_regular_file(12, &format!("lib{}.so", index), 30 + index, b"");
+        }
+        image
+    }
+
+    #[test]
+    fn lookup_nested_path() {
+        let data_source = usr_image(false).build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        let entry = vdfs.lookup("/usr/bin/foo").unwrap();
+        assert_eq!(entry.object_id, 20);
+        assert!(matches!(entry.record_type, CatalogTreeRecordType::File));
+        match entry.record {
+            CatalogRecord::File(file_record) => assert_eq!(file_record.data_fork.size_in_bytes, 3),
+            CatalogRecord::Folder(_) => panic!("Not a file"),
+        }
+        assert_eq!(vdfs.lookup("/usr/lib/lib7.so").unwrap().object_id, 37);
+        assert_eq!(vdfs.lookup("/README").unwrap().object_id, 21);
+        assert!(vdfs.lookup("/usr/bin").unwrap().is_folder());
+        assert_eq!(vdfs.lookup("/").unwrap().object_id, 1);
+    }
+
+    #[test]
+    fn lookup_skips_dot_components() {
+        let data_source = usr_image(false).build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert_eq!(vdfs.lookup("usr/./lib/../bin//foo").unwrap().object_id, 20);
+        assert_eq!(vdfs.lookup("/../usr").unwrap().object_id, 10);
+    }
+
+    #[test]
+    fn lookup_missing_path() {
+        let data_source = usr_image(false).build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert!(matches!(
+            vdfs.lookup("/usr/bin/bar"),
+            Err(VdfsError::PathNotFound(_))
+        ));
+        // Prefix of an existing name
+        assert!(matches!(
+            vdfs.lookup("/usr/lib/lib"),
+            Err(VdfsError::PathNotFound(_))
+        ));
+        assert!(matches!(
+            vdfs.lookup("/usr/BIN/foo"),
+            Err(VdfsError::PathNotFound(_))
+        ));
+        assert!(matches!(
+            vdfs.lookup("/README/foo"),
+            Err(VdfsError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn lookup_case_insensitive() {
+        let data_source = usr_image(true).build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert_eq!(vdfs.lookup("/USR/Bin/FOO").unwrap().object_id, 20);
+        assert_eq!(vdfs.lookup("/readme").unwrap().object_id, 21);
+    }
+
+    #[test]
+    fn lookup_resolves_hard_link() {
+        let mut image = TestImage::new();
+        image
+            .add_regular_file(12, "", 12, b"shared")
+            .add_hard_link(1, "first", 12, FileType::Regular)
+            .add_hard_link(1, "second", 12, FileType::Regular);
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        for path in ["/first", "/second"] {
+            let entry = vdfs.lookup(path).unwrap();
+            assert_eq!(entry.object_id, 12);
+            assert!(matches!(entry.record_type, CatalogTreeRecordType::File));
+        }
+    }
+}
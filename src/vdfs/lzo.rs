@@ -0,0 +1,338 @@
+// LZO1X decompressor, port of lzo1x_decompress_safe from the Linux kernel
+
+const M2_MAX_OFFSET: usize = 0x0800;
+const M4_OFFSET_BASE: usize = 0x4000;
+
+#[derive(Debug)]
+pub enum LzoError {
+    InputOverrun,
+    OutputOverrun,
+    LookBehindOverrun,
+    InputNotConsumed,
+    LengthOverflow,
+    EofMarkerCorrupted,
+}
+
+struct LzoDecoder<'a> {
+    input: &'a [u8],
+    input_position: usize,
+    output: Vec<u8>,
+    max_output_size: usize,
+}
+
+/// Decompresses LZO1X stream, the output is limited by `max_output_size` bytes.
+pub fn decompress(input: &[u8], max_output_size: usize) -> Result<Vec<u8>, LzoError> {
+    LzoDecoder {
+        input,
+        input_position: 0,
+        output: Vec::with_capacity(max_output_size),
+        max_output_size,
+    }
+    .decompress()
+}
+
+impl<'a> LzoDecoder<'a> {
+    fn decompress(mut self) -> Result<Vec<u8>, LzoError> {
+        if self.input.len() < 3 {
+            return Err(LzoError::InputOverrun);
+        }
+
+        // Count of literals copied after the last instruction, 4 means a long literal run
+        let mut state: usize = 0;
+
+        if self.input[0] > 17 {
+            self.input_position += 1;
+            let literals_count = self.input[0] as usize - 17;
+            self.copy_literals(literals_count)?;
+            state = literals_count.min(4);
+        }
+
+        loop {
+            let t = self.read_byte()?;
+            let distance;
+            let length;
+            let next;
+
+            if t < 16 {
+                if state == 0 {
+                    // Literal run
+                    let literals_count = if t == 0 {
+                        self.read_extended_length(15)?
+                    } else {
+                        t
+                    } + 3;
+                    self.copy_literals(literals_count)?;
+                    state = 4;
+                    continue;
+                } else if state != 4 {
+                    // Two bytes match after a short literal run
+                    next = t & 3;
+                    distance = 1 + (t >> 2) + (self.read_byte()? << 2);
+                    length = 2;
+                } else {
+                    // Three bytes match after a long literal run
+                    next = t & 3;
+                    distance = 1 + M2_MAX_OFFSET + (t >> 2) + (self.read_byte()? << 2);
+                    length = 3;
+                }
+            } else if t >= 64 {
+                next = t & 3;
+                distance = 1 + ((t >> 2) & 7) + (self.read_byte()? << 3);
+                length = (t >> 5) + 1;
+            } else if t >= 32 {
+                length = match t & 31 {
+                    0 => self.read_extended_length(31)?,
+                    bits => bits,
+                } + 2;
+                let value = self.read_le16()?;
+                next = value & 3;
+                distance = 1 + (value >> 2);
+            } else {
+                let distance_high = (t & 8) << 11;
+                length = match t & 7 {
+                    0 => self.read_extended_length(7)?,
+                    bits => bits,
+                } + 2;
+                let value = self.read_le16()?;
+                next = value & 3;
+                if distance_high + (value >> 2) == 0 {
+                    return self.finish(length);
+                }
+                distance = distance_high + (value >> 2) + M4_OFFSET_BASE;
+            }
+
+            self.copy_match(distance, length)?;
+            self.copy_literals(next)?;
+            state = next;
+        }
+    }
+
+    fn finish(self, length: usize) -> Result<Vec<u8>, LzoError> {
+        if length != 3 {
+            Err(LzoError::EofMarkerCorrupted)
+        } else if self.input_position < self.input.len() {
+            Err(LzoError::InputNotConsumed)
+        } else {
+            Ok(self.output)
+        }
+    }
+
+    fn need_input(&self, count: usize) -> Result<(), LzoError> {
+        if self.input.len() - self.input_position < count {
+            return Err(LzoError::InputOverrun);
+        }
+        Ok(())
+    }
+
+    fn need_output(&self, count: usize) -> Result<(), LzoError> {
+        if self.max_output_size - self.output.len() < count {
+            return Err(LzoError::OutputOverrun);
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<usize, LzoError> {
+        self.need_input(1)?;
+        let byte = self.input[self.input_position];
+        self.input_position += 1;
+        Ok(byte as usize)
+    }
+
+    fn read_le16(&mut self) -> Result<usize, LzoError> {
+        let low = self.read_byte()?;
+        let high = self.read_byte()?;
+        Ok(low | (high << 8))
+    }
+
+    /// Length continues with a zero byte for every 255 and a final non-zero byte.
+    fn read_extended_length(&mut self, base: usize) -> Result<usize, LzoError> {
+        let mut length = base;
+        loop {
+            match self.read_byte()? {
+                0 => length = length.checked_add(255).ok_or(LzoError::LengthOverflow)?,
+                byte => return length.checked_add(byte).ok_or(LzoError::LengthOverflow),
+            }
+        }
+    }
+
+    fn copy_literals(&mut self, count: usize) -> Result<(), LzoError> {
+        self.need_input(count)?;
+        self.need_output(count)?;
+        let literals = &self.input[self.input_position..self.input_position + count];
+        self.output.extend_from_slice(literals);
+        self.input_position += count;
+        Ok(())
+    }
+
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<(), LzoError> {
+        if distance > self.output.len() {
+            return Err(LzoError::LookBehindOverrun);
+        }
+        self.need_output(length)?;
+        // Source and destination can overlap, so copy byte by byte
+        let start = self.output.len() - distance;
+        for i in 0..length {
+            let byte = self.output[start + i];
+            self.output.push(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Streams are assembled by hand following the LZO1X instruction encoding
+    const EOF_MARKER: [u8; 3] = [0x11, 0x00, 0x00];
+
+    fn stream(parts: &[&[u8]]) -> Vec<u8> {
+        let mut stream = parts.concat();
+        stream.extend_from_slice(&EOF_MARKER);
+        stream
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn first_literal_run() {
+        let input = stream(&[&[17 + 5], b"hello"]);
+        assert_eq!(decompress(&input, 5).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn short_literal_run() {
+        let input = stream(&[&[0x02], b"abcde"]);
+        assert_eq!(decompress(&input, 5).unwrap(), b"abcde");
+    }
+
+    #[test]
+    fn extended_literal_run() {
+        // 15 + 2 + 3 literals
+        let literals = pattern(20);
+        let input = stream(&[&[0x00, 0x02], &literals]);
+        assert_eq!(decompress(&input, 20).unwrap(), literals);
+
+        // Zero byte adds 255: 15 + 255 + 1 + 3 literals
+        let literals = pattern(274);
+        let input = stream(&[&[0x00, 0x00, 0x01], &literals]);
+        assert_eq!(decompress(&input, 274).unwrap(), literals);
+    }
+
+    #[test]
+    fn m1_match_after_short_literal_run() {
+        // Distance 2, length 2
+        let input = stream(&[&[17 + 2], b"ab", &[0x04, 0x00]]);
+        assert_eq!(decompress(&input, 4).unwrap(), b"abab");
+    }
+
+    #[test]
+    fn m1_match_after_long_literal_run() {
+        // 15 + 7 * 255 + 246 + 3 literals, then length 3 at distance 0x801
+        let literals = pattern(0x801);
+        let mut length = vec![0x00];
+        length.extend_from_slice(&[0x00; 7]);
+        length.push(246);
+        let input = stream(&[&length, &literals, &[0x00, 0x00]]);
+
+        let mut expected = literals.clone();
+        expected.extend_from_slice(&literals[..3]);
+        assert_eq!(decompress(&input, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn m2_overlapping_match() {
+        // Length 6, distance 3
+        let input = stream(&[&[17 + 3], b"abc", &[0xa8, 0x00]]);
+        assert_eq!(decompress(&input, 9).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn m2_match_with_trailing_literals() {
+        // Length 3, distance 3, then 2 literals
+        let input = stream(&[&[17 + 3], b"abc", &[0x4a, 0x00], b"de"]);
+        assert_eq!(decompress(&input, 8).unwrap(), b"abcabcde");
+    }
+
+    #[test]
+    fn m3_match() {
+        // Length 4, distance 4
+        let input = stream(&[&[17 + 4], b"abcd", &[0x22, 0x0c, 0x00]]);
+        assert_eq!(decompress(&input, 8).unwrap(), b"abcdabcd");
+    }
+
+    #[test]
+    fn m3_extended_length_match() {
+        // Length 31 + 1 + 2, distance 1
+        let input = stream(&[&[17 + 1], b"a", &[0x20, 0x01, 0x00, 0x00]]);
+        assert_eq!(decompress(&input, 35).unwrap(), vec![b'a'; 35]);
+    }
+
+    #[test]
+    fn m4_match() {
+        // 15 + 64 * 255 + 47 + 3 literals, then length 4 at distance 0x4001
+        let literals = pattern(0x4001);
+        let mut length = vec![0x00];
+        length.extend_from_slice(&[0x00; 64]);
+        length.push(47);
+        let input = stream(&[&length, &literals, &[0x12, 0x04, 0x00]]);
+
+        let mut expected = literals.clone();
+        expected.extend_from_slice(&literals[..4]);
+        assert_eq!(decompress(&input, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert!(matches!(
+            decompress(&[17 + 5, b'h', b'e'], 5),
+            Err(LzoError::InputOverrun)
+        ));
+        let input = stream(&[&[17 + 5], b"hello"]);
+        assert!(matches!(
+            decompress(&input[..input.len() - 1], 5),
+            Err(LzoError::InputOverrun)
+        ));
+    }
+
+    #[test]
+    fn distance_before_output_start() {
+        // Length 3, distance 2 after a single literal
+        let input = stream(&[&[17 + 1], b"a", &[0x44, 0x00]]);
+        assert!(matches!(
+            decompress(&input, 4),
+            Err(LzoError::LookBehindOverrun)
+        ));
+    }
+
+    #[test]
+    fn output_limit() {
+        let input = stream(&[&[17 + 5], b"hello"]);
+        assert!(matches!(
+            decompress(&input, 4),
+            Err(LzoError::OutputOverrun)
+        ));
+    }
+
+    #[test]
+    fn data_after_eof_marker() {
+        let mut input = stream(&[&[17 + 5], b"hello"]);
+        input.push(0);
+        assert!(matches!(
+            decompress(&input, 5),
+            Err(LzoError::InputNotConsumed)
+        ));
+    }
+
+    #[test]
+    fn corrupted_eof_marker() {
+        // M4 with zero distance and length 4 instead of 3
+        let input = [&[17 + 5][..], b"hello", &[0x12, 0x00, 0x00]].concat();
+        assert!(matches!(
+            decompress(&input, 5),
+            Err(LzoError::EofMarkerCorrupted)
+        ));
+    }
+}
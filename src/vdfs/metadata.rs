@@ -0,0 +1,130 @@
+use std::time::SystemTime;
+
+use super::{lookup::*, *};
+
+pub const PERMISSIONS_MASK: u16 = 0o7777;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    object_id: u64,
+    file_mode: u16,
+    uid: u32,
+    gid: u32,
+    links_count: u64,
+    flags: u32,
+    size_in_bytes: u64,
+    creation_time: Vdfs4Timespec,
+    modification_time: Vdfs4Timespec,
+    access_time: Vdfs4Timespec,
+}
+
+impl Metadata {
+    pub fn object_id(&self) -> u64 {
+        self.object_id
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        FileType::from_u16(self.file_mode)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        FileType::Directory.is_file_type(self.file_mode)
+    }
+
+    pub fn is_file(&self) -> bool {
+        FileType::Regular.is_file_type(self.file_mode)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        FileType::SymbolicLink.is_file_type(self.file_mode)
+    }
+
+    /// Full file mode including the file type bits.
+    pub fn mode(&self) -> u16 {
+        self.file_mode
+    }
+
+    pub fn permissions(&self) -> u16 {
+        self.file_mode & PERMISSIONS_MASK
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn links_count(&self) -> u64 {
+        self.links_count
+    }
+
+    /// Size of the data fork in bytes, folders have zero size.
+    pub fn len(&self) -> u64 {
+        self.size_in_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size_in_bytes == 0
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn has_flag(&self, flag: VdfsFileFlags) -> bool {
+        if let VdfsFileFlags::HardLink = flag {
+            if self.links_count > 1 {
+                return true;
+            }
+        }
+        self.flags & (1 << (flag as u32)) != 0
+    }
+
+    pub fn created(&self) -> Option<SystemTime> {
+        self.creation_time.to_system_time()
+    }
+
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modification_time.to_system_time()
+    }
+
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.access_time.to_system_time()
+    }
+}
+
+impl From<&CatalogEntry> for Metadata {
+    fn from(entry: &CatalogEntry) -> Self {
+        let common = entry.record.get_common();
+        let size_in_bytes = match &entry.record {
+            CatalogRecord::Folder(_) => 0,
+            CatalogRecord::File(file_record) => file_record.data_fork.size_in_bytes,
+        };
+        Metadata {
+            object_id: entry.object_id,
+            file_mode: common.file_mode,
+            uid: common.uid,
+            gid: common.gid,
+            links_count: common.links_count,
+            flags: common.flags,
+            size_in_bytes,
+            creation_time: common.creation_time,
+            modification_time: common.modification_time,
+            access_time: common.access_time,
+        }
+    }
+}
+
+impl CatalogEntry {
+    pub fn metadata(&self) -> Metadata {
+        Metadata::from(self)
+    }
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    pub fn metadata(&self, path: &str) -> Result<Metadata, VdfsError> {
+        Ok(self.lookup(path)?.metadata())
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+use super::{lookup::*, *};
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// Inodes that were unlinked while still open, in the order of the orphan list.
+    /// The list starts at the OrphanInodes special inode and is chained through `next_orphan_id`,
+    /// every inode of it is looked up by its nameless record.
+    /// A broken chain does not fail the read, the orphans before the break are returned
+    /// together with the error.
+    pub fn read_orphan_inodes(&self) -> Result<(Vec<CatalogEntry>, Option<VdfsError>), VdfsError> {
+        let head_id = SpecialInodeIds::OrphanInodes as u64;
+        let mut next_orphan_id = match self.read_orphan_inode(head_id) {
+            Ok(head_entry) => head_entry.record.get_common().next_orphan_id,
+            Err(VdfsError::OrphanInodeNotFound(_)) => VDFS4_INVALID_NODE_ID,
+            Err(e) => return Ok((Vec::new(), Some(e))),
+        };
+
+        let mut visited = BTreeSet::from([head_id]);
+        let mut orphans = Vec::new();
+        while next_orphan_id != VDFS4_INVALID_NODE_ID {
+            if !visited.insert(next_orphan_id) {
+                return Ok((orphans, Some(VdfsError::OrphanListLoop(next_orphan_id))));
+            }
+            let entry = match self.read_orphan_inode(next_orphan_id) {
+                Ok(entry) => entry,
+                Err(e) => return Ok((orphans, Some(e))),
+            };
+            next_orphan_id = entry.record.get_common().next_orphan_id;
+            orphans.push(entry);
+        }
+        Ok((orphans, None))
+    }
+
+    fn read_orphan_inode(&self, object_id: u64) -> Result<CatalogEntry, VdfsError> {
+        let record = self
+            .get_catalog_tree()?
+            .find_inode_record(object_id)?
+            .filter(|record| {
+                matches!(
+                    record.data.get_record_type(),
+                    CatalogTreeRecordType::Folder | CatalogTreeRecordType::File
+                )
+            })
+            .ok_or(VdfsError::OrphanInodeNotFound(object_id))?;
+        self.get_catalog_entry(&record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    const HEAD_ID: u64 = SpecialInodeIds::OrphanInodes as u64;
+
+    /// Orphan list with the inodes chained in the given order, the last one points to `last_next_id`.
+    fn orphan_list_image(orphan_ids: &[u64], last_next_id: u64) -> TestDataSource {
+        let mut image = TestImage::new();
+        image.add_regular_file(1, "named", 10, b"");
+        let next_ids = orphan_ids.iter().skip(1).copied().chain([last_next_id]);
+        let mut head_record = folder_record();
+        head_record.next_orphan_id = orphan_ids.first().copied().unwrap_or(last_next_id);
+        image.add_folder(HEAD_ID, "", HEAD_ID, head_record);
+        for (object_id, next_id) in orphan_ids.iter().zip(next_ids) {
+            let mut record = file_record(FileType::Regular, 0);
+            record.common.flags = 1 << VdfsFileFlags::OrphanInode as u32;
+            record.common.next_orphan_id = next_id;
+            image.add_file(*object_id, "", *object_id, record);
+        }
+        image.build()
+    }
+
+    fn orphan_ids(orphans: &[CatalogEntry]) -> Vec<u64> {
+        orphans.iter().map(|orphan| orphan.object_id).collect()
+    }
+
+    #[test]
+    fn walk_orphan_list() {
+        let data_source = orphan_list_image(&[30, 20, 40], VDFS4_INVALID_NODE_ID);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let (orphans, broken_chain) = vdfs.read_orphan_inodes().unwrap();
+        assert_eq!(orphan_ids(&orphans), [30, 20, 40]);
+        assert!(broken_chain.is_none());
+    }
+
+    #[test]
+    fn orphan_list_without_head() {
+        let mut image = TestImage::new();
+        image.add_regular_file(1, "named", 10, b"");
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let (orphans, broken_chain) = vdfs.read_orphan_inodes().unwrap();
+        assert!(orphans.is_empty());
+        assert!(broken_chain.is_none());
+    }
+
+    #[test]
+    fn orphan_list_loop() {
+        let data_source = orphan_list_image(&[20, 21, 22], 21);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let (orphans, broken_chain) = vdfs.read_orphan_inodes().unwrap();
+        assert_eq!(orphan_ids(&orphans), [20, 21, 22]);
+        assert!(matches!(broken_chain, Some(VdfsError::OrphanListLoop(21))));
+
+        // Back to the head
+        let data_source = orphan_list_image(&[20], HEAD_ID);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        let (orphans, broken_chain) = vdfs.read_orphan_inodes().unwrap();
+        assert_eq!(orphan_ids(&orphans), [20]);
+        assert!(matches!(
+            broken_chain,
+            Some(VdfsError::OrphanListLoop(HEAD_ID))
+        ));
+    }
+
+    #[test]
+    fn orphan_list_with_missing_inode() {
+        // The named file is not an orphan inode record
+        for missing_id in [99, 10] {
+            let data_source = orphan_list_image(&[20], missing_id);
+            let vdfs = Vdfs::open(&data_source).unwrap();
+            let (orphans, broken_chain) = vdfs.read_orphan_inodes().unwrap();
+            assert_eq!(orphan_ids(&orphans), [20]);
+            assert!(
+                matches!(broken_chain, Some(VdfsError::OrphanInodeNotFound(id)) if id == missing_id)
+            );
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use super::*;
+
+#[derive(Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub object_id: u64,
+    pub record_type: CatalogTreeRecordType,
+    pub file_type: Option<FileType>,
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// Lists the folder by walking catalog records that have it as a parent.
+    pub fn read_dir(
+        &self,
+        object_id: u64,
+    ) -> Result<impl Iterator<Item = Result<DirEntry, VdfsError>> + '_, VdfsError> {
+        let key = Vdfs4CatTreeKey::from_parent_and_name(object_id, &[]);
+        let records = self
+            .get_catalog_tree()?
+            .records_from(key)?
+            .take_while(move |record| record.data.parent_id == object_id)
+            // Skip the hard linked inode, it is stored as a child of itself
+            .filter(|record| record.data.object_id != record.data.parent_id)
+            .map(|record| self.get_dir_entry(&record));
+        Ok(records)
+    }
+
+    pub fn get_dir_entry(
+        &self,
+        record: &DataPointer<Vdfs4CatTreeKey>,
+    ) -> Result<DirEntry, VdfsError> {
+        let record_type = record.data.get_record_type();
+        let file_mode = match record_type {
+            CatalogTreeRecordType::Folder | CatalogTreeRecordType::File => {
+                let folder_record: Vdfs4CatalogFolderRecord =
+                    record.get_record_value(self.data_source)?.data;
+                folder_record.file_mode
+            }
+            CatalogTreeRecordType::HLink => {
+                let hlink_record: Vdfs4CatalogHlinkRecord =
+                    record.get_record_value(self.data_source)?.data;
+                hlink_record.file_mode
+            }
+            _ => 0,
+        };
+        Ok(DirEntry {
+            name: record.data.get_name_string(),
+            object_id: record.data.object_id,
+            record_type,
+            file_type: FileType::from_u16(file_mode),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    fn names(vdfs: &Vdfs<impl DataSourceSource>, object_id: u64) -> Vec<String> {
+        vdfs.read_dir(object_id)
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect()
+    }
+
+    #[test]
+    fn read_dir_spanning_several_leaves() {
+        let mut image = TestImage::new();
+        image
+            .add_folder(1, "a", 10, folder_record())
+            .add_folder(1, "b", 11, folder_record())
+            .add_regular_file(11, "after", 40, b"");
+        for index in 0..9 {
+            image.add_regular_file(10, &format!("file{}", index), 20 + index, b"");
+        }
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        let expected: Vec<String> = (0..9).map(|index| format!("file{}", index)).collect();
+        assert_eq!(names(&vdfs, 10), expected);
+        assert_eq!(names(&vdfs, 11), ["after"]);
+        assert_eq!(names(&vdfs, 1), ["a", "b"]);
+    }
+
+    #[test]
+    fn read_dir_entry_types() {
+        let mut image = TestImage::new();
+        image
+            .add_folder(1, "folder", 10, folder_record())
+            .add_file(1, "link", 11, file_record(FileType::SymbolicLink, 0))
+            .add_regular_file(12, "", 12, b"")
+            .add_hard_link(1, "hard", 12, FileType::Regular);
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        let entries: Vec<DirEntry> = vdfs.read_dir(1).unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "folder");
+        assert!(matches!(entries[0].file_type, Some(FileType::Directory)));
+        assert_eq!(entries[1].name, "hard");
+        assert_eq!(entries[1].object_id, 12);
+        assert!(matches!(
+            entries[1].record_type,
+            CatalogTreeRecordType::HLink
+        ));
+        assert!(matches!(entries[1].file_type, Some(FileType::Regular)));
+        assert_eq!(entries[2].name, "link");
+        assert!(matches!(entries[2].file_type, Some(FileType::SymbolicLink)));
+        // The hard linked inode is a child of itself, but not an entry of its own folder
+        assert!(names(&vdfs, 12).is_empty());
+    }
+
+    #[test]
+    fn read_empty_dir() {
+        let mut image = TestImage::new();
+        image
+            .add_folder(1, "empty", 10, folder_record())
+            .add_folder(1, "full", 11, folder_record())
+            .add_regular_file(11, "file", 20, b"");
+        let data_source = image.build();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+        assert!(names(&vdfs, 10).is_empty());
+        assert!(names(&vdfs, 20).is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use md5::Md5;
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::Sha256;
+use std::collections::BTreeSet;
+
+use super::{compressed::calc_hash, lookup::*, *};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureVerdict {
+    NotSigned,
+    Ok,
+    Invalid,
+    /// Signature of the hashes is valid, but the chunk with the index does not match its hash.
+    ChunkMismatch(u64),
+}
+
+impl From<bool> for SignatureVerdict {
+    fn from(is_valid: bool) -> Self {
+        if is_valid {
+            SignatureVerdict::Ok
+        } else {
+            SignatureVerdict::Invalid
+        }
+    }
+}
+
+/// Path of a signed file and its verdict, or the error that prevented the check.
+pub type FileSignatureVerdict = (String, Result<SignatureVerdict, VdfsError>);
+
+/// Reads an RSA public key, PEM or DER encoded, PKCS#1 or SubjectPublicKeyInfo.
+pub fn read_public_key(path: &str) -> Result<RsaPublicKey, VdfsError> {
+    let key_data = fs::read(path)
+        .map_err(|e| VdfsError::PublicKeyError(format!("Cannot read {}: {}", path, e)))?;
+    let public_key = match std::str::from_utf8(&key_data) {
+        Ok(pem) if pem.contains("-----BEGIN") => RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+            .ok(),
+        _ => RsaPublicKey::from_public_key_der(&key_data)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(&key_data))
+            .ok(),
+    };
+    public_key.ok_or_else(|| VdfsError::PublicKeyError(format!("Not an RSA public key: {}", path)))
+}
+
+/// PKCS#1 v1.5 signature over the digest of the data.
+pub fn verify_rsa_signature(
+    public_key: &RsaPublicKey,
+    auth_type: VdfsFileAuth,
+    signed_data: &[u8],
+    signature: &[u8],
+) -> bool {
+    let scheme = match auth_type {
+        VdfsFileAuth::Md5 => Pkcs1v15Sign::new::<Md5>(),
+        VdfsFileAuth::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+        VdfsFileAuth::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+    };
+    let digest = calc_hash(auth_type, signed_data);
+    public_key.verify(scheme, &digest, signature).is_ok()
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// `sb_hash` is the signature of the superblock bytes that precede it.
+    pub fn verify_super_block_signature(
+        &self,
+        public_key: &RsaPublicKey,
+    ) -> Result<SignatureVerdict, VdfsError> {
+        let super_block = &self.super_blocks.super_block;
+        let signature_length = match VdfsFileSignatureType::from_u8(super_block.sign_type) {
+            None | Some(VdfsFileSignatureType::None) => return Ok(SignatureVerdict::NotSigned),
+            Some(signature_type) => signature_type.get_signature_length() as usize,
+        };
+        let auth_type = match VdfsFileAuth::from_hash_type(super_block.hash_type) {
+            Some(auth_type) => auth_type,
+            None => return Ok(SignatureVerdict::Invalid),
+        };
+
+        let encoded = self.data_source.serialize(super_block)?;
+        let signed_length = encoded.len() - VDFS4_MAX_CRYPTED_HASH_LEN - CRC32_SIZE;
+        Ok(SignatureVerdict::from(verify_rsa_signature(
+            public_key,
+            auth_type,
+            &encoded[..signed_length],
+            &super_block.sb_hash[..signature_length],
+        )))
+    }
+
+    /// Checks signatures of all signed files, returns their paths with verdicts.
+    /// Files that cannot be read get the error instead, hard linked files are checked once.
+    pub fn verify_file_signatures(
+        &self,
+        public_key: &RsaPublicKey,
+    ) -> Result<Vec<FileSignatureVerdict>, VdfsError> {
+        let mut folder_paths = BTreeMap::new();
+        let mut checked_files = BTreeSet::new();
+        let mut verdicts = Vec::new();
+
+        for record in self.get_catalog_tree()?.all_records_iterator()? {
+            let path = match folder_paths.get(&record.data.parent_id) {
+                Some(parent_path) => format!("{}/{}", parent_path, record.data.get_name_string()),
+                None if record.data.parent_id == SpecialInodeIds::RootDirObject as u64 => {
+                    String::new()
+                }
+                None => format!("#{}", record.data.object_id),
+            };
+
+            match record.data.get_record_type() {
+                CatalogTreeRecordType::Folder => {
+                    folder_paths.insert(record.data.object_id, path);
+                }
+                CatalogTreeRecordType::File | CatalogTreeRecordType::HLink => {
+                    if record.data.parent_id == record.data.object_id {
+                        continue;
+                    }
+                    let entry = match self.get_catalog_entry(&record) {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            verdicts.push((path, Err(e)));
+                            continue;
+                        }
+                    };
+                    let file_record = match entry.record {
+                        CatalogRecord::File(file_record) => file_record,
+                        CatalogRecord::Folder(_) => continue,
+                    };
+                    let common = &file_record.common;
+                    let may_be_signed = common.has_file_flag(VdfsFileFlags::AuthFile)
+                        || common.has_file_flag(VdfsFileFlags::ReadOnlyAuth)
+                        || common.has_file_flag(VdfsFileFlags::CompressedFile);
+                    if !may_be_signed || !checked_files.insert(entry.object_id) {
+                        continue;
+                    }
+                    let verdict = self
+                        .open_compressed_file_by_record(entry.object_id, file_record)
+                        .and_then(|compressed_file| compressed_file.verify_signature(public_key));
+                    match verdict {
+                        Ok(SignatureVerdict::NotSigned) => {}
+                        verdict => verdicts.push((path, verdict)),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(verdicts)
+    }
+}
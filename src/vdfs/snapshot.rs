@@ -0,0 +1,191 @@
+use super::*;
+
+/// State of one of the base tables, valid or not.
+#[derive(Debug, Clone, Copy)]
+pub struct BaseTableInfo {
+    pub index: u32,
+    pub position: u64,
+    pub mount_count: u64,
+    pub sync_count: u32,
+    pub has_valid_header: bool,
+    pub has_valid_crc32: bool,
+    pub extended_tables_count: usize,
+    pub is_current: bool,
+}
+
+impl BaseTableInfo {
+    pub fn get_version(&self) -> u64 {
+        (self.mount_count << 32) | self.sync_count as u64
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.has_valid_header && self.has_valid_crc32
+    }
+}
+
+impl<'a, S: DataSourceSource> Vdfs<'a, S> {
+    /// Opens the image at the given base table instead of the newest one.
+    pub fn open_at_base_table(
+        data_source: &'a DataSource<S>,
+        index: u32,
+    ) -> Result<Vdfs<'a, S>, VdfsError> {
+        let mut vdfs = Self::new(data_source)?;
+        vdfs.select_base_table(index)?;
+        vdfs.init_btrees()?;
+        Ok(vdfs)
+    }
+
+    /// Describes both base tables, including the damaged ones.
+    pub fn list_base_tables(&self) -> Result<Vec<BaseTableInfo>, VdfsError> {
+        let mut base_tables = Vec::new();
+        for index in 0..VDFS4_SNAPSHOT_BASE_TABLES {
+            let position = self.get_base_table_offset(index);
+            let base_table: DataPointer<Vdfs4BaseTable> = self.data_source.read_at(position)?;
+            let descriptor = &base_table.data.descriptor;
+
+            let has_valid_header = self.check_base_table_header(&base_table.data);
+            let has_valid_crc32 = has_valid_header
+                && self.check_crc32_of_snapshot_descriptor(position, descriptor)?;
+            let extended_tables_count = if has_valid_crc32 {
+                self.read_extended_tables(&base_table)?.len()
+            } else {
+                0
+            };
+            let is_current = self
+                .current_base_table
+                .is_some_and(|current| current.position == position);
+
+            base_tables.push(BaseTableInfo {
+                index,
+                position,
+                mount_count: descriptor.mount_count,
+                sync_count: descriptor.sync_count,
+                has_valid_header,
+                has_valid_crc32,
+                extended_tables_count,
+                is_current,
+            });
+        }
+        Ok(base_tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+
+    /// Image with the folders of the base table, later snapshots are written over it.
+    fn base_image(names: &[&str]) -> TestDataSource {
+        snapshot_builder(names).build()
+    }
+
+    fn snapshot_builder(names: &[&str]) -> TestImage {
+        let mut image = TestImage::new();
+        for (index, name) in names.iter().enumerate() {
+            image.add_folder(1, name, 10 + index as u64, folder_record());
+        }
+        image
+    }
+
+    fn has_path<S: DataSourceSource>(vdfs: &Vdfs<S>, path: &str) -> bool {
+        match vdfs.lookup(path) {
+            Ok(_) => true,
+            Err(VdfsError::PathNotFound(_)) => false,
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn extended_tables_overlay_base_table() {
+        let data_source = base_image(&["a"]);
+        snapshot_builder(&["a", "b"]).write_extended_table(&data_source, 0, 0, SYNC_COUNT + 1, 20);
+        snapshot_builder(&["b", "c"]).write_extended_table(&data_source, 0, 1, SYNC_COUNT + 2, 30);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert_eq!(vdfs.get_current_extended_tables().len(), 2);
+        assert!(!has_path(&vdfs, "/a"));
+        assert!(has_path(&vdfs, "/b"));
+        assert!(has_path(&vdfs, "/c"));
+        let base_tables = vdfs.list_base_tables().unwrap();
+        assert_eq!(base_tables[0].extended_tables_count, 2);
+    }
+
+    #[test]
+    fn stale_extended_table_ends_the_chain() {
+        let data_source = base_image(&["a"]);
+        // Not newer than the base table
+        snapshot_builder(&["b"]).write_extended_table(&data_source, 0, 0, SYNC_COUNT, 20);
+        snapshot_builder(&["c"]).write_extended_table(&data_source, 0, 1, SYNC_COUNT + 2, 30);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert!(vdfs.get_current_extended_tables().is_empty());
+        assert!(has_path(&vdfs, "/a"));
+        assert!(!has_path(&vdfs, "/b"));
+        assert!(!has_path(&vdfs, "/c"));
+    }
+
+    #[test]
+    fn damaged_extended_table_is_ignored() {
+        let data_source = base_image(&["a"]);
+        snapshot_builder(&["b"]).write_extended_table(&data_source, 0, 0, SYNC_COUNT + 1, 20);
+        let extended_table_position = get_base_table_position(0) + VDFS4_SNAPSHOT_EXT_SIZE as u64;
+        let records_position = extended_table_position + size_of::<Vdfs4ExtendedTable>() as u64;
+        data_source.write_at(&0xffu8, records_position).unwrap();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert!(vdfs.get_current_extended_tables().is_empty());
+        assert!(has_path(&vdfs, "/a"));
+        assert!(!has_path(&vdfs, "/b"));
+    }
+
+    #[test]
+    fn newer_base_table_is_current() {
+        let data_source = base_image(&["a"]);
+        snapshot_builder(&["b"]).write_snapshot(&data_source, 1, SYNC_COUNT + 1, 20);
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert!(has_path(&vdfs, "/b"));
+        let base_tables = vdfs.list_base_tables().unwrap();
+        assert!(base_tables.iter().all(|base_table| base_table.is_valid()));
+        assert!(!base_tables[0].is_current);
+        assert!(base_tables[1].is_current);
+        assert!(base_tables[0].get_version() < base_tables[1].get_version());
+
+        let vdfs = Vdfs::open_at_base_table(&data_source, 0).unwrap();
+        assert!(has_path(&vdfs, "/a"));
+        assert!(!has_path(&vdfs, "/b"));
+        assert!(matches!(
+            Vdfs::open_at_base_table(&data_source, 2),
+            Err(VdfsError::InvalidBaseTable(2))
+        ));
+    }
+
+    #[test]
+    fn damaged_base_table_is_skipped() {
+        let data_source = base_image(&["a"]);
+        snapshot_builder(&["b"]).write_snapshot(&data_source, 1, SYNC_COUNT + 1, 20);
+        let records_position = get_base_table_position(1) + size_of::<Vdfs4BaseTable>() as u64;
+        data_source.write_at(&0xffu8, records_position).unwrap();
+        let vdfs = Vdfs::open(&data_source).unwrap();
+
+        assert!(has_path(&vdfs, "/a"));
+        let base_tables = vdfs.list_base_tables().unwrap();
+        assert!(base_tables[0].is_current);
+        assert!(base_tables[1].has_valid_header);
+        assert!(!base_tables[1].has_valid_crc32);
+        assert!(matches!(
+            Vdfs::open_at_base_table(&data_source, 1),
+            Err(VdfsError::InvalidBaseTable(1))
+        ));
+
+        // Without a valid base table the image cannot be opened
+        data_source
+            .write_at(&0xffu8, get_base_table_position(0))
+            .unwrap();
+        assert!(matches!(
+            Vdfs::open(&data_source),
+            Err(VdfsError::BaseTableIsMissing())
+        ));
+    }
+}
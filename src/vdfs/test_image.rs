@@ -0,0 +1,639 @@
+//! Images assembled in memory for the unit tests.
+//! Blocks and B-tree nodes are 4 KiB, every tree is an index root over its leaves.
+
+use std::{collections::BTreeSet, io::Cursor};
+
+use super::*;
+
+pub const BLOCK_SIZE: u64 = 4096;
+const LOG_BLOCK_SIZE: u8 = 12;
+/// Primary superblock follows its two redundant copies.
+pub const SUPER_BLOCK_OFFSET: u64 = 1024;
+const EXT_SUPER_BLOCK_OFFSET: u64 = 1536;
+/// Each half of the tables extent holds a base table and two extended tables.
+const TABLES_BEGIN: u64 = 1;
+const TABLES_LENGTH: u64 = 6;
+const META_BEGIN: u64 = 8;
+const META_LENGTH: u64 = 56;
+/// File data is stored from this block on.
+pub const DATA_BEGIN: u64 = 64;
+const IMAGE_BLOCKS: u64 = 128;
+
+/// Snapshot version written by build, the base table records and the nodes carry it too.
+pub const SYNC_COUNT: u32 = 1;
+const MOUNT_COUNT: u32 = 1;
+
+const HEAD_NODE_ID: u32 = 0;
+const ROOT_NODE_ID: u32 = 1;
+
+pub type TestDataSource = DataSource<Cursor<Vec<u8>>>;
+
+pub struct TestImage {
+    pub super_block: Vdfs4SuperBlock,
+    /// Leaves are split after this count of records, to get trees with several leaves
+    pub records_per_leaf: usize,
+    data_source: TestDataSource,
+    catalog: Vec<(Vdfs4CatTreeKey, Vec<u8>)>,
+    extents: Vec<(Vdfs4ExtTreeKey, Vec<u8>)>,
+    xattrs: Vec<(Vdfs4XattrTreeKey, Vec<u8>)>,
+    next_data_block: u64,
+}
+
+/// Directory with 0755 mode and zero timestamps.
+pub fn folder_record() -> Vdfs4CatalogFolderRecord {
+    let time = Vdfs4Timespec {
+        seconds: 0,
+        seconds_high: 0,
+        nanoseconds: 0,
+    };
+    Vdfs4CatalogFolderRecord {
+        flags: 0,
+        generation: 0,
+        total_items_count: 0,
+        links_count: 1,
+        next_orphan_id: VDFS4_INVALID_NODE_ID,
+        file_mode: FileType::Directory.to_u16() | 0o755,
+        pad: 0,
+        uid: 0,
+        gid: 0,
+        creation_time: time,
+        modification_time: time,
+        access_time: time,
+    }
+}
+
+/// File with 0644 mode and no extents in the fork.
+pub fn file_record(file_type: FileType, size_in_bytes: u64) -> Vdfs4CatalogFileRecord {
+    Vdfs4CatalogFileRecord {
+        common: Vdfs4CatalogFolderRecord {
+            file_mode: file_type.to_u16() | 0o644,
+            ..folder_record()
+        },
+        data_fork: Vdfs4Fork {
+            size_in_bytes,
+            total_blocks_count: size_in_bytes.div_ceil(BLOCK_SIZE),
+            extents: std::array::from_fn(|_| iextent(0, 0, 0)),
+        },
+    }
+}
+
+pub fn iextent(iblock: u64, begin: u64, length: u64) -> Vdfs4Iextent {
+    Vdfs4Iextent {
+        extent: Vdfs4Extent { begin, length },
+        iblock,
+    }
+}
+
+impl TestImage {
+    /// Image with the root folder only.
+    pub fn new() -> Self {
+        let image_size = (IMAGE_BLOCKS * BLOCK_SIZE) as usize;
+        let mut image = Self {
+            super_block: super_block(),
+            records_per_leaf: 4,
+            data_source: DataSource::from_source(Cursor::new(vec![0u8; image_size])),
+            catalog: Vec::new(),
+            extents: Vec::new(),
+            xattrs: Vec::new(),
+            next_data_block: DATA_BEGIN,
+        };
+        image.add_folder(
+            SpecialInodeIds::RootDirObject as u64,
+            "root",
+            SpecialInodeIds::Root as u64,
+            folder_record(),
+        );
+        image
+    }
+
+    pub fn add_folder(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        record: Vdfs4CatalogFolderRecord,
+    ) -> &mut Self {
+        let value = self.encode(&record);
+        self.add_catalog_record(
+            parent_id,
+            name,
+            object_id,
+            CatalogTreeRecordType::Folder,
+            value,
+        )
+    }
+
+    pub fn add_file(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        record: Vdfs4CatalogFileRecord,
+    ) -> &mut Self {
+        let value = self.encode(&record);
+        self.add_catalog_record(
+            parent_id,
+            name,
+            object_id,
+            CatalogTreeRecordType::File,
+            value,
+        )
+    }
+
+    /// Regular file with the content in a single extent of the fork.
+    pub fn add_regular_file(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        content: &[u8],
+    ) -> &mut Self {
+        let mut record = file_record(FileType::Regular, content.len() as u64);
+        record.data_fork.extents[0] = self.add_data(0, content);
+        self.add_file(parent_id, name, object_id, record)
+    }
+
+    pub fn add_symlink(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        target: &str,
+    ) -> &mut Self {
+        let mut record = file_record(FileType::SymbolicLink, target.len() as u64);
+        record.common.file_mode = FileType::SymbolicLink.to_u16() | 0o777;
+        record.data_fork.extents[0] = self.add_data(0, target.as_bytes());
+        self.add_file(parent_id, name, object_id, record)
+    }
+
+    /// Name of the hard linked inode that is stored as a nameless child of itself.
+    pub fn add_hard_link(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        file_type: FileType,
+    ) -> &mut Self {
+        let value = self.encode(&Vdfs4CatalogHlinkRecord {
+            file_mode: file_type.to_u16() | 0o644,
+            pad1: 0,
+            pad2: 0,
+        });
+        self.add_catalog_record(
+            parent_id,
+            name,
+            object_id,
+            CatalogTreeRecordType::HLink,
+            value,
+        )
+    }
+
+    pub fn add_catalog_record(
+        &mut self,
+        parent_id: u64,
+        name: &str,
+        object_id: u64,
+        record_type: CatalogTreeRecordType,
+        value: Vec<u8>,
+    ) -> &mut Self {
+        let mut key = Vdfs4CatTreeKey {
+            object_id,
+            record_type: record_type as u8,
+            ..Vdfs4CatTreeKey::from_parent_and_name(parent_id, name.as_bytes())
+        };
+        key.gen_key = generic_key(VDFS4_CAT_KEY_MAX_LEN, value.len());
+        self.catalog.push((key, value));
+        self
+    }
+
+    /// Stores the content in new blocks, returns the extent that maps them at `iblock`.
+    pub fn add_data(&mut self, iblock: u64, content: &[u8]) -> Vdfs4Iextent {
+        let begin = self.next_data_block;
+        let length = (content.len() as u64).div_ceil(BLOCK_SIZE);
+        self.next_data_block += length;
+        assert!(self.next_data_block <= IMAGE_BLOCKS, "Image is full");
+        self.data_source
+            .write_bytes_at(content, begin * BLOCK_SIZE)
+            .unwrap();
+        iextent(iblock, begin, length)
+    }
+
+    pub fn add_extent(&mut self, object_id: u64, extent: &Vdfs4Iextent) -> &mut Self {
+        let mut key = Vdfs4ExtTreeKey::from_object_id_and_iblock(object_id, extent.iblock);
+        let value = self.encode(&extent.extent);
+        key.gen_key = generic_key(VDFS4_EXT_KEY_MAX_LEN, value.len());
+        self.extents.push((key, value));
+        self
+    }
+
+    pub fn add_xattr(&mut self, object_id: u64, name: &str, value: &[u8]) -> &mut Self {
+        let mut key = Vdfs4XattrTreeKey::from_object_id_and_name(object_id, name.as_bytes());
+        key.gen_key = generic_key(VDFS4_XATTR_KEY_MAX_LEN, value.len());
+        self.xattrs.push((key, value.to_vec()));
+        self
+    }
+
+    /// Writes the superblocks and the snapshot into the first base table.
+    pub fn build(self) -> TestDataSource {
+        self.write_super_blocks();
+        self.write_snapshot(&self.data_source, 0, SYNC_COUNT, 0);
+        self.data_source
+    }
+
+    /// Writes the trees from `first_meta_iblock` on and the base table `table_index`
+    /// that points to them. File data stays in the image of this builder.
+    pub fn write_snapshot(
+        &self,
+        data_source: &TestDataSource,
+        table_index: u64,
+        sync_count: u32,
+        first_meta_iblock: u64,
+    ) {
+        let trees = self.write_trees(data_source, sync_count, first_meta_iblock);
+        let position = get_base_table_position(table_index);
+        let record_size = size_of::<Vdfs4BaseTableRecord>() as u64;
+
+        let mut base_table = Vdfs4BaseTable {
+            descriptor: snapshot_descriptor(VDFS4_SNAPSHOT_BASE_TABLE, sync_count),
+            last_page_index: [0; VDFS4_SF_NR as usize],
+            translation_table_offsets: [0; VDFS4_SF_NR as usize],
+        };
+        let mut offset = size_of::<Vdfs4BaseTable>() as u64;
+        for (btree_type, meta_iblocks) in trees {
+            base_table.translation_table_offsets[btree_type.get_index()] = offset;
+            base_table.last_page_index[btree_type.get_index()] = meta_iblocks.len() as u64 - 1;
+            for meta_iblock in meta_iblocks {
+                let record = Vdfs4BaseTableRecord {
+                    meta_iblock,
+                    sync_count,
+                    mount_count: MOUNT_COUNT,
+                };
+                data_source.write_at(&record, position + offset).unwrap();
+                offset += record_size;
+            }
+        }
+        base_table.descriptor.checksum_offset = offset;
+        data_source.write_at(&base_table, position).unwrap();
+        write_crc32(data_source, position, offset);
+    }
+
+    /// Writes the trees from `first_meta_iblock` on and the extended table of the base table
+    /// `table_index` that remaps all their nodes.
+    pub fn write_extended_table(
+        &self,
+        data_source: &TestDataSource,
+        table_index: u64,
+        extended_table_index: u64,
+        sync_count: u32,
+        first_meta_iblock: u64,
+    ) {
+        let trees = self.write_trees(data_source, sync_count, first_meta_iblock);
+        // Base table and the extended tables before this one fit into a block each
+        let position = get_base_table_position(table_index)
+            + (extended_table_index + 1) * VDFS4_SNAPSHOT_EXT_SIZE as u64;
+
+        let mut offset = size_of::<Vdfs4ExtendedTable>() as u64;
+        let mut records_count = 0;
+        for (btree_type, meta_iblocks) in trees {
+            for (node_id, meta_iblock) in meta_iblocks.into_iter().enumerate() {
+                let record = Vdfs4ExtendedRecord {
+                    object_id: btree_type as u64,
+                    table_index: node_id as u64,
+                    meta_iblock,
+                };
+                offset += data_source.write_at(&record, position + offset).unwrap();
+                records_count += 1;
+            }
+        }
+        let mut extended_table = Vdfs4ExtendedTable {
+            descriptor: snapshot_descriptor(VDFS4_SNAPSHOT_EXTENDED_TABLE, sync_count),
+            records_count,
+            pad: 0,
+        };
+        extended_table.descriptor.checksum_offset = offset;
+        data_source.write_at(&extended_table, position).unwrap();
+        write_crc32(data_source, position, offset);
+    }
+
+    fn write_super_blocks(&self) {
+        let mut super_block = self.super_block;
+        super_block.checksum = self.calc_crc32(&super_block);
+        for copy_index in 0..3 {
+            self.data_source
+                .write_at(
+                    &super_block,
+                    copy_index * size_of::<Vdfs4SuperBlock>() as u64,
+                )
+                .unwrap();
+        }
+
+        let mut meta = [Vdfs4Extent {
+            begin: 0,
+            length: 0,
+        }; VDFS4_META_BTREE_EXTENTS];
+        meta[0] = Vdfs4Extent {
+            begin: META_BEGIN,
+            length: META_LENGTH,
+        };
+        let count_objects = |record_type: CatalogTreeRecordType| {
+            self.catalog
+                .iter()
+                .filter(|(key, _)| key.record_type == record_type as u8)
+                .map(|(key, _)| key.object_id)
+                .collect::<BTreeSet<_>>()
+                .len() as u64
+        };
+        let ext_super_block = Vdfs4ExtendedSuperBlock {
+            files_count: count_objects(CatalogTreeRecordType::File),
+            folders_count: count_objects(CatalogTreeRecordType::Folder),
+            volume_body: Vdfs4Extent {
+                begin: 0,
+                length: IMAGE_BLOCKS,
+            },
+            mount_counter: MOUNT_COUNT,
+            sync_counter: 0,
+            umount_counter: 0,
+            generation: 0,
+            debug_area: Vdfs4Extent {
+                begin: 0,
+                length: 0,
+            },
+            meta_tbc: 0,
+            pad: 0,
+            tables: Vdfs4Extent {
+                begin: TABLES_BEGIN,
+                length: TABLES_LENGTH,
+            },
+            meta,
+            extension: Vdfs4Extent {
+                begin: 0,
+                length: 0,
+            },
+            volume_blocks_count: IMAGE_BLOCKS,
+            crc: 0,
+            volume_uuid: [0; 16],
+            _reserved: [0; 7],
+            kbytes_written: 0,
+            meta_hashtable_area: Vdfs4Extent {
+                begin: 0,
+                length: 0,
+            },
+            reserved: [0; 860],
+            checksum: 0,
+        };
+        update_ext_super_block(&self.data_source, ext_super_block, |_| {});
+    }
+
+    /// Returns the meta blocks of the nodes of each tree, indexed by the node id.
+    fn write_trees(
+        &self,
+        data_source: &TestDataSource,
+        sync_count: u32,
+        first_meta_iblock: u64,
+    ) -> Vec<(BtreeType, Vec<u64>)> {
+        let mut next_meta_iblock = first_meta_iblock;
+        let case_insensitive = self.super_block.case_insensitive;
+        vec![
+            (
+                BtreeType::CatalogTree,
+                self.write_tree(
+                    data_source,
+                    &self.catalog,
+                    case_insensitive,
+                    sync_count,
+                    &mut next_meta_iblock,
+                ),
+            ),
+            (
+                BtreeType::ExtentsTree,
+                self.write_tree(
+                    data_source,
+                    &self.extents,
+                    case_insensitive,
+                    sync_count,
+                    &mut next_meta_iblock,
+                ),
+            ),
+            (
+                BtreeType::XAttrTree,
+                self.write_tree(
+                    data_source,
+                    &self.xattrs,
+                    case_insensitive,
+                    sync_count,
+                    &mut next_meta_iblock,
+                ),
+            ),
+        ]
+    }
+
+    /// Head node, root index node and the leaves in the key order.
+    fn write_tree<T: VdfsBtreeKey>(
+        &self,
+        data_source: &TestDataSource,
+        records: &[(T, Vec<u8>)],
+        case_insensitive: bool,
+        sync_count: u32,
+        next_meta_iblock: &mut u64,
+    ) -> Vec<u64> {
+        let mut order: Vec<usize> = (0..records.len()).collect();
+        order.sort_by(|first, second| {
+            records[*first]
+                .0
+                .compare(&records[*second].0, case_insensitive)
+                .unwrap()
+        });
+        let leaves: Vec<&[usize]> = order.chunks(self.records_per_leaf).collect();
+        let nodes_count = leaves.len() as u32 + 2;
+        let meta_iblocks: Vec<u64> = (*next_meta_iblock..).take(nodes_count as usize).collect();
+        *next_meta_iblock += nodes_count as u64;
+        assert!(*next_meta_iblock <= META_LENGTH, "Meta area is full");
+        let node_position =
+            |node_id: u32| (META_BEGIN + meta_iblocks[node_id as usize]) * BLOCK_SIZE;
+
+        let head_node = Vdfs4HeadBtreeNode {
+            magic: *b"eHND",
+            version: [sync_count, MOUNT_COUNT],
+            root_bnode_id: ROOT_NODE_ID,
+            btree_height: 2,
+            padding: [0; 2],
+        };
+        data_source
+            .write_at(&head_node, node_position(HEAD_NODE_ID))
+            .unwrap();
+
+        let mut index_records = Vec::new();
+        for (leaf_index, leaf) in leaves.iter().enumerate() {
+            let node_id = leaf_index as u32 + ROOT_NODE_ID + 1;
+            let leaf_records: Vec<Vec<u8>> = leaf
+                .iter()
+                .map(|index| {
+                    let (key, value) = &records[*index];
+                    let mut record = self.encode_key(key);
+                    record.extend_from_slice(value);
+                    record
+                })
+                .collect();
+            let first_key = &records[leaf[0]].0;
+            let mut index_record = self.encode_key(first_key);
+            index_record.extend(self.encode(&GenericIndexValue { node_id }));
+            // Index records keep the key, but their value is the child node id
+            let record_len = index_record.len() as u16;
+            index_record[6..8].copy_from_slice(&record_len.to_le_bytes());
+            index_records.push(index_record);
+
+            let previous_node_id = if leaf_index == 0 { 0 } else { node_id - 1 };
+            let next_node_id = if node_id + 1 < nodes_count {
+                node_id + 1
+            } else {
+                VDFS4_INVALID_NODE_ID as u32
+            };
+            self.write_node(
+                data_source,
+                node_position(node_id),
+                [node_id, previous_node_id, next_node_id],
+                sync_count,
+                &leaf_records,
+            );
+        }
+        self.write_node(
+            data_source,
+            node_position(ROOT_NODE_ID),
+            [ROOT_NODE_ID, 0, VDFS4_INVALID_NODE_ID as u32],
+            sync_count,
+            &index_records,
+        );
+        meta_iblocks
+    }
+
+    /// `node_ids` are the id of the node, of the previous and of the next one on the level.
+    fn write_node(
+        &self,
+        data_source: &TestDataSource,
+        position: u64,
+        node_ids: [u32; 3],
+        sync_count: u32,
+        records: &[Vec<u8>],
+    ) {
+        let mut node = vec![0u8; BLOCK_SIZE as usize];
+        let write_offset = |node: &mut Vec<u8>, index: usize, offset: usize| {
+            let offset_position = BLOCK_SIZE as usize - CRC32_SIZE - 4 * (index + 1);
+            node[offset_position..offset_position + 4]
+                .copy_from_slice(&(offset as u32).to_le_bytes());
+        };
+        let mut offset = size_of::<Vdfs4GeneralBtreeNode>();
+        for (index, record) in records.iter().enumerate() {
+            node[offset..offset + record.len()].copy_from_slice(record);
+            write_offset(&mut node, index, offset);
+            offset += record.len();
+        }
+        // Offset after the last record points to the free space
+        write_offset(&mut node, records.len(), offset);
+        let offsets_start = BLOCK_SIZE as usize - CRC32_SIZE - 4 * (records.len() + 1);
+        assert!(offset <= offsets_start, "Records do not fit into the node");
+
+        let descriptor = Vdfs4GeneralBtreeNode {
+            magic: *b"Nd\0\0",
+            version: [sync_count, MOUNT_COUNT],
+            free_space: (offsets_start - offset) as u16,
+            recs_count: records.len() as u16,
+            node_id: node_ids[0],
+            nrev_node_id: node_ids[1],
+            next_node_id: node_ids[2],
+            node_type: 0,
+        };
+        let descriptor = self.encode(&descriptor);
+        node[..descriptor.len()].copy_from_slice(&descriptor);
+        data_source.write_bytes_at(&node, position).unwrap();
+    }
+
+    /// Key padded to its key_len, the value follows it.
+    fn encode_key<T: VdfsBtreeKey>(&self, key: &T) -> Vec<u8> {
+        let mut encoded = self.encode(key);
+        encoded.resize(key.get_value_offset() as usize, 0);
+        encoded
+    }
+
+    pub fn encode<T: bincode::Encode>(&self, value: &T) -> Vec<u8> {
+        self.data_source.serialize(value).unwrap()
+    }
+
+    fn calc_crc32<T: bincode::Encode>(&self, value: &T) -> u32 {
+        let encoded = self.encode(value);
+        vdfs_crc::crc32(&encoded[..encoded.len() - CRC32_SIZE])
+    }
+}
+
+/// Rewrites the extended superblock with a valid CRC after `update`.
+pub fn update_ext_super_block(
+    data_source: &TestDataSource,
+    mut ext_super_block: Vdfs4ExtendedSuperBlock,
+    update: impl FnOnce(&mut Vdfs4ExtendedSuperBlock),
+) {
+    update(&mut ext_super_block);
+    let encoded = data_source.serialize(&ext_super_block).unwrap();
+    ext_super_block.checksum = vdfs_crc::crc32(&encoded[..encoded.len() - CRC32_SIZE]);
+    data_source
+        .write_at(&ext_super_block, EXT_SUPER_BLOCK_OFFSET)
+        .unwrap();
+}
+
+pub fn get_base_table_position(table_index: u64) -> u64 {
+    (TABLES_BEGIN + table_index * TABLES_LENGTH / 2) * BLOCK_SIZE
+}
+
+fn super_block() -> Vdfs4SuperBlock {
+    Vdfs4SuperBlock {
+        signature: *b"VDFS",
+        layout_version: *VDFS4_LAYOUT_VERSION_2007,
+        maximum_blocks_count: IMAGE_BLOCKS,
+        creation_timestamp: folder_record().creation_time,
+        volume_uuid: [0; 16],
+        volume_name: [0; 16],
+        mkfs_version: [0; 64],
+        unused: [0; 40],
+        log_block_size: LOG_BLOCK_SIZE,
+        log_super_page_size: LOG_BLOCK_SIZE,
+        log_erase_block_size: LOG_BLOCK_SIZE,
+        case_insensitive: false,
+        read_only: true,
+        image_crc32_present: false,
+        force_full_decomp_decrypt: false,
+        hash_type: 0,
+        encryption_flags: 0,
+        sign_type: VdfsFileSignatureType::None as u8,
+        reserved: [0; 54],
+        exsb_checksum: 0,
+        basetable_checksum: 0,
+        meta_hashtable_checksum: 0,
+        image_inode_count: 0,
+        pad: 0,
+        sb_hash: [0; VDFS4_MAX_CRYPTED_HASH_LEN],
+        checksum: 0,
+    }
+}
+
+fn generic_key(key_len: u64, value_len: usize) -> Vdfs4GenericKey {
+    Vdfs4GenericKey {
+        magic: [0; 4],
+        key_len: key_len as u16,
+        record_len: (key_len as usize + value_len) as u16,
+    }
+}
+
+fn snapshot_descriptor(signature: &str, sync_count: u32) -> Vdfs4SnapshotDescriptor {
+    Vdfs4SnapshotDescriptor {
+        signature: signature.as_bytes().try_into().unwrap(),
+        sync_count,
+        mount_count: MOUNT_COUNT as u64,
+        checksum_offset: 0,
+    }
+}
+
+/// CRC of the `length` bytes at `position` is stored right after them.
+fn write_crc32(data_source: &TestDataSource, position: u64, length: u64) {
+    let body = data_source.read_bytes_at(position, length).unwrap();
+    data_source
+        .write_at(&vdfs_crc::crc32(&body), position + length)
+        .unwrap();
+}
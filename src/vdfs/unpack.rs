@@ -1,54 +1,123 @@
-use libflate::{gzip, zlib};
-use std::io::Read;
+use std::{
+    collections::BTreeSet,
+    ffi::CString,
+    io,
+    os::unix::{self, ffi::OsStrExt, fs::PermissionsExt},
+};
+
+use super::{
+    compressed::{AuthVerdict, ContentVerdict},
+    file::VdfsFile,
+    lookup::*,
+    *,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct UnpackOptions {
+    /// Store symbolic links as text files with the link target
+    pub symlinks_as_files: bool,
+    /// Where to record special files that cannot be created, "<output>.special_files" by default
+    pub special_files_manifest_path: Option<String>,
+    /// Do not apply mode, owner and timestamps from the image
+    pub skip_metadata: bool,
+    /// Numeric owner remapping from the image uid to the local one
+    pub uid_map: BTreeMap<u32, u32>,
+    /// Numeric group remapping from the image gid to the local one
+    pub gid_map: BTreeMap<u32, u32>,
+    /// Do not restore extended attributes
+    pub skip_xattrs: bool,
+    /// Do not try to set security and trusted attributes when not running as root
+    pub skip_privileged_xattrs: bool,
+    /// Where to dump extended attributes that were not set, "<output>.xattrs" by default
+    pub xattrs_dump_path: Option<String>,
+    /// Extract inodes from the orphan list into "<output>/lost+found", named by object id
+    pub extract_orphans: bool,
+}
 
-use super::*;
+struct UnpackContext<'o> {
+    options: &'o UnpackOptions,
+    root_path: String,
+    folders_map: BTreeMap<u64, String>,
+    hard_links_map: BTreeMap<u64, String>,
+    /// Lines of the special files manifest: path, type, major, minor and mode
+    special_files_manifest: Vec<String>,
+    /// Folders get their metadata after all children are written
+    folders_metadata: Vec<(String, u64, Vdfs4CatalogFolderRecord)>,
+    /// Extended attributes that were not set, in getfattr --dump format
+    xattrs_dump: String,
+    is_root: bool,
+    orphan_ids: BTreeSet<u64>,
+}
 
 impl<'a, S: DataSourceSource> Vdfs<'a, S> {
     pub fn unpack(&self, output_path: &str) -> Result<(), VdfsError> {
-        let root_path = String::from(output_path);
-        let mut folders_map = BTreeMap::<u64, String>::new();
+        self.unpack_with_options(output_path, &UnpackOptions::default())
+    }
 
-        fs::create_dir_all(&root_path).unwrap();
-        for record in self
-            .catalog_btree
-            .as_ref()
-            .unwrap()
-            .all_records_iterator()
-            .unwrap()
-        {
+    pub fn unpack_with_options(
+        &self,
+        output_path: &str,
+        options: &UnpackOptions,
+    ) -> Result<(), VdfsError> {
+        let mut context = UnpackContext {
+            options,
+            root_path: String::from(output_path),
+            folders_map: BTreeMap::new(),
+            hard_links_map: BTreeMap::new(),
+            special_files_manifest: Vec::new(),
+            folders_metadata: Vec::new(),
+            xattrs_dump: String::new(),
+            is_root: unsafe { libc::geteuid() } == 0,
+            orphan_ids: BTreeSet::new(),
+        };
+
+        let (orphans, broken_chain) = self.read_orphan_inodes()?;
+        if let Some(error) = broken_chain {
+            println!(
+                "Orphan list is broken after {} inodes: {:?}",
+                orphans.len(),
+                error
+            );
+        }
+        for orphan in &orphans {
+            let metadata = orphan.metadata();
+            println!(
+                "Orphan inode {}: {}, {} bytes",
+                orphan.object_id,
+                metadata
+                    .file_type()
+                    .map_or('?', |file_type| file_type.get_type_char()),
+                metadata.len()
+            );
+            context.orphan_ids.insert(orphan.object_id);
+        }
+
+        fs::create_dir_all(&context.root_path).unwrap();
+        for record in self.get_catalog_tree()?.all_records_iterator()? {
             if let CatalogTreeRecordType::Folder = record.data.get_record_type() {
                 let folder_record: Vdfs4CatalogFolderRecord =
                     record.get_record_value(self.data_source)?.data;
-                let mut path = String::new();
-                let parent_folder_id = record.data.parent_id;
 
-                if folder_record.next_orphan_id != VDFS4_INVALID_NODE_ID {
-                    todo!();
-                }
-                if parent_folder_id == VDFS4_INVALID_NODE_ID {
-                    path.push_str(&root_path);
-                } else {
-                    let parent_folder = folders_map.get(&parent_folder_id).unwrap();
-                    path.push_str(parent_folder);
+                if context.orphan_ids.contains(&record.data.object_id) {
+                    continue;
                 }
-                add_path_component(&mut path, record.data.get_name_string().as_str());
+                let path = context.get_path(&record.data)?;
                 fs::create_dir(&path).unwrap();
-                folders_map.insert(record.data.object_id, path);
+                context
+                    .folders_map
+                    .insert(record.data.object_id, path.clone());
+                context
+                    .folders_metadata
+                    .push((path, record.data.object_id, folder_record));
             }
         }
 
-        for record in self
-            .catalog_btree
-            .as_ref()
-            .unwrap()
-            .all_records_iterator()
-            .unwrap()
-        {
+        for record in self.get_catalog_tree()?.all_records_iterator()? {
             if record.data.parent_id == record.data.object_id {
-                println!(
-                    "Record object_id == record_parent_id, scipping: {:?}",
-                    record
-                );
+                // Hard linked inode, it is unpacked with the first link to it
+                continue;
+            }
+            if context.orphan_ids.contains(&record.data.object_id) {
                 continue;
             }
 
@@ -56,220 +125,346 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
                 CatalogTreeRecordType::File => {
                     let catalog_file_record: Vdfs4CatalogFileRecord =
                         record.get_record_value(self.data_source)?.data;
-                    let parent_id = record.data.parent_id;
-                    let mut path = String::new();
-                    //println!("FILE: {:?} {:?}", record.data.get_name_string(), catalog_file_record);
-
-                    if catalog_file_record.common.is_file_type(FileType::Regular) {
-                        if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::AuthFile)
-                        {
-                            println!("AuthFile");
-                            continue;
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::CompressedFile)
-                        {
-                            println!("CompressedFile");
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::EncryptedFile)
-                        {
-                            println!("EncryptedFile");
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::HardLink)
-                        {
-                            println!("HardLink");
-                            continue;
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::HasBlocksInExttree)
-                        {
-                            println!("HasBlocksInExttree");
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::Immutable)
-                        {
-                            println!("Immutable");
-                            continue;
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::OrphanInode)
-                        {
-                            println!("OrphanInode");
-                            continue;
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::ProfiledFile)
-                        {
-                            println!("ProfiledFile");
-                            continue;
-                        } else if catalog_file_record
-                            .common
-                            .has_file_flag(VdfsFileFlags::ReadOnlyAuth)
-                        {
-                            println!("ReadOnlyAuth");
-                            continue;
-                        }
-
-                        if parent_id != VDFS4_INVALID_NODE_ID {
-                            let parent_folder = folders_map
-                                .get(&parent_id)
-                                .ok_or(VdfsError::CannotFindParentFolder)?;
-                            add_path_component(&mut path, &parent_folder);
-                        } else {
-                            path.push_str(&root_path);
-                        }
-                        add_path_component(&mut path, &record.data.get_name_string());
-
-                        println!("Regular file: {}", path);
-
-                        self.unpack_file(&path, record.data.object_id, &catalog_file_record)?;
-                    } else {
-                        println!(
-                            "Scipping special file: {:?}",
-                            catalog_file_record.common.get_file_type().unwrap()
-                        );
-                    }
+                    let path = context.get_path(&record.data)?;
+                    self.unpack_catalog_file(
+                        &mut context,
+                        &path,
+                        record.data.object_id,
+                        &catalog_file_record,
+                    )?;
                 }
                 CatalogTreeRecordType::HLink => {
-                    let catalog_hlink_record: Vdfs4CatalogHlinkRecord =
-                        record.get_record_value(self.data_source)?.data;
-                    println!(
-                        "HLINK: {:?} {:?}",
-                        record.data.get_name_string(),
-                        catalog_hlink_record
-                    );
+                    let path = context.get_path(&record.data)?;
+                    let inode_entry = self.get_hard_link_inode_entry(record.data.object_id)?;
+                    match inode_entry.record {
+                        CatalogRecord::File(catalog_file_record) => self.unpack_catalog_file(
+                            &mut context,
+                            &path,
+                            inode_entry.object_id,
+                            &catalog_file_record,
+                        )?,
+                        CatalogRecord::Folder(_) => {
+                            println!("Skipping hard link to folder: {}", path)
+                        }
+                    }
                 }
                 CatalogTreeRecordType::UnpackInode => todo!(),
                 _ => {}
             }
         }
 
+        if options.extract_orphans && !orphans.is_empty() {
+            self.unpack_orphans(&mut context, orphans)?;
+        }
+
+        // Children first, so that read-only folders do not block anything
+        for (path, object_id, folder_record) in
+            std::mem::take(&mut context.folders_metadata).iter().rev()
+        {
+            let xattrs = self.read_xattrs_to_restore(&context, *object_id)?;
+            context.apply_metadata(path, folder_record, &xattrs)?;
+        }
+
+        if !context.xattrs_dump.is_empty() {
+            let dump_path = options
+                .xattrs_dump_path
+                .clone()
+                .unwrap_or_else(|| format!("{}.xattrs", output_path));
+            println!("Extended attributes that were not set: {}", dump_path);
+            fs::write(&dump_path, &context.xattrs_dump).map_err(|e| {
+                VdfsError::FileWriteError(format!("Cannot write extended attributes dump: {}", e))
+            })?;
+        }
+
+        if !context.special_files_manifest.is_empty() {
+            let manifest_path = options
+                .special_files_manifest_path
+                .clone()
+                .unwrap_or_else(|| format!("{}.special_files", output_path));
+            println!("Special files manifest: {}", manifest_path);
+            let mut manifest = context.special_files_manifest.join("\n");
+            manifest.push('\n');
+            fs::write(&manifest_path, manifest).map_err(|e| {
+                VdfsError::FileWriteError(format!("Cannot write special files manifest: {}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Orphans have no name, they are stored as "lost+found/#<object id>" like fsck does.
+    fn unpack_orphans(
+        &self,
+        context: &mut UnpackContext,
+        orphans: Vec<CatalogEntry>,
+    ) -> Result<(), VdfsError> {
+        let mut lost_and_found_path = context.root_path.clone();
+        add_path_component(&mut lost_and_found_path, "lost+found");
+        fs::create_dir_all(&lost_and_found_path).map_err(|e| {
+            VdfsError::FileWriteError(format!("Cannot create lost+found folder: {}", e))
+        })?;
+
+        for orphan in orphans {
+            let mut path = lost_and_found_path.clone();
+            add_path_component(&mut path, &format!("#{}", orphan.object_id));
+            match orphan.record {
+                CatalogRecord::Folder(folder_record) => {
+                    fs::create_dir(&path).map_err(|e| {
+                        VdfsError::FileWriteError(format!("Cannot create folder: {}", e))
+                    })?;
+                    context
+                        .folders_metadata
+                        .push((path, orphan.object_id, folder_record));
+                }
+                CatalogRecord::File(catalog_file_record) => {
+                    self.unpack_catalog_file(
+                        context,
+                        &path,
+                        orphan.object_id,
+                        &catalog_file_record,
+                    )?;
+                }
+            }
+        }
         Ok(())
     }
 
-    fn unpack_file(
+    fn unpack_catalog_file(
         &self,
+        context: &mut UnpackContext,
         path: &str,
         file_object_id: u64,
         catalog_file_record: &Vdfs4CatalogFileRecord,
     ) -> Result<(), VdfsError> {
-        if catalog_file_record
+        let hard_link = catalog_file_record
             .common
-            .has_file_flag(VdfsFileFlags::CompressedFile)
-        {
-            self.unpack_compressed_file(path, file_object_id, catalog_file_record)
+            .has_file_flag(VdfsFileFlags::HardLink);
+        if hard_link {
+            if let Some(first_link_path) = context.hard_links_map.get(&file_object_id) {
+                println!("Hard link: {} => {}", path, first_link_path);
+                return fs::hard_link(first_link_path, path).map_err(|e| {
+                    VdfsError::FileWriteError(format!("Cannot create hard link: {}", e))
+                });
+            }
+        }
+
+        if catalog_file_record.common.is_file_type(FileType::Regular) {
+            let common = &catalog_file_record.common;
+            // Orphans from the orphan list are unpacked into lost+found
+            if common.has_file_flag(VdfsFileFlags::Immutable)
+                || (common.has_file_flag(VdfsFileFlags::OrphanInode)
+                    && !context.orphan_ids.contains(&file_object_id))
+                || common.has_file_flag(VdfsFileFlags::ProfiledFile)
+            {
+                return Ok(());
+            }
+
+            println!("Regular file: {}", path);
+
+            if !self.unpack_file(path, file_object_id, catalog_file_record)? {
+                return Ok(());
+            }
         } else if catalog_file_record
             .common
-            .has_file_flag(VdfsFileFlags::EncryptedFile)
+            .is_file_type(FileType::SymbolicLink)
+        {
+            self.unpack_symlink(
+                path,
+                file_object_id,
+                catalog_file_record,
+                context.options.symlinks_as_files,
+            )?;
+        } else if !self.unpack_special_file(context, path, &catalog_file_record.common)? {
+            return Ok(());
+        }
+
+        let xattrs = self.read_xattrs_to_restore(context, file_object_id)?;
+        context.apply_metadata(path, &catalog_file_record.common, &xattrs)?;
+        if hard_link {
+            context
+                .hard_links_map
+                .insert(file_object_id, String::from(path));
+        }
+        Ok(())
+    }
+
+    /// Returns false if the file was skipped and not created.
+    pub fn unpack_file(
+        &self,
+        path: &str,
+        file_object_id: u64,
+        catalog_file_record: &Vdfs4CatalogFileRecord,
+    ) -> Result<bool, VdfsError> {
+        if catalog_file_record
+            .common
+            .has_file_flag(VdfsFileFlags::CompressedFile)
+            || catalog_file_record
+                .common
+                .has_file_flag(VdfsFileFlags::EncryptedFile)
+            || catalog_file_record
+                .common
+                .has_file_flag(VdfsFileFlags::AuthFile)
+            || catalog_file_record
+                .common
+                .has_file_flag(VdfsFileFlags::ReadOnlyAuth)
         {
             self.unpack_compressed_file(path, file_object_id, catalog_file_record)
         } else {
-            self.unpack_raw_file(path, file_object_id, catalog_file_record)
+            self.unpack_raw_file(path, file_object_id, catalog_file_record)?;
+            Ok(true)
         }
     }
 
+    /// Returns false if the file is encrypted and there is no key.
     fn unpack_compressed_file(
         &self,
         path: &str,
         file_object_id: u64,
         catalog_file_record: &Vdfs4CatalogFileRecord,
-    ) -> Result<(), VdfsError> {
-        let temp_raw_file =
-            self.create_temp_file_and_write_raw_data(path, file_object_id, catalog_file_record)?;
-        let temp_file_data_source = DataSource::from_source(temp_raw_file);
-        let descriptor_size = size_of::<Vdfs4CompressedFileDescr>() as u64;
-        let extent_size = size_of::<Vdfs4CompressedExtent>() as u64;
-        let temp_raw_file_size = catalog_file_record.data_fork.size_in_bytes;
-
-        let descriptor: Vdfs4CompressedFileDescr = temp_file_data_source
-            .read_at(temp_raw_file_size - descriptor_size as u64)?
-            .data;
-        let compressed_flag = catalog_file_record
-            .common
-            .has_file_flag(VdfsFileFlags::CompressedFile);
-        let compression = descriptor.get_compression();
-        let signature_type = descriptor.get_signature_type();
-        let auth_type = descriptor.get_auth();
-        let extents_count = descriptor.extents_num as u64;
-
-        if auth_type.is_some() {
-            println!("SKIPPING FILE WITH AUTH: {}", path);
-            return Ok(());
-            //todo!("Implement auth")
+    ) -> Result<bool, VdfsError> {
+        let compressed_file = self.open_compressed_file_by_record(
+            file_object_id,
+            Box::new(catalog_file_record.clone()),
+        )?;
+
+        if compressed_file.is_encrypted() && self.get_encryption_key().is_none() {
+            println!("Skipping encrypted file, no key: {}", path);
+            return Ok(false);
         }
-        if !compressed_flag || compression.is_none() {
-            return Err(VdfsError::CannotDecompressFileWithoutCompression);
+
+        let mut auth_verdict = match compressed_file.get_descriptor().get_auth() {
+            Some(_) => AuthVerdict::Ok,
+            None => AuthVerdict::NotAuthenticated,
+        };
+
+        let mut crc32_digest = vdfs_crc::crc32_digest();
+        let mut unpacked_length = 0;
+
+        let mut output_file = File::create(path)
+            .map_err(|e| VdfsError::FileWriteError(format!("Cannot create file: {}", e)))?;
+        for chunk_index in 0..compressed_file.get_chunks_count() {
+            let raw_chunk = compressed_file.read_raw_chunk(chunk_index)?;
+            if auth_verdict == AuthVerdict::Ok
+                && !compressed_file.verify_raw_chunk(chunk_index, &raw_chunk)?
+            {
+                auth_verdict = AuthVerdict::MismatchAtChunk(chunk_index);
+            }
+            let chunk = compressed_file.unpack_chunk(chunk_index, raw_chunk)?;
+            crc32_digest.update(&chunk);
+            unpacked_length += chunk.len() as u64;
+            output_file
+                .write_all(chunk.as_slice())
+                .map_err(|e| VdfsError::FileWriteError(format!("Cannot append to file: {}", e)))?;
         }
 
-        let mut first_extent_position =
-            temp_raw_file_size - descriptor_size as u64 - extent_size * extents_count;
-        if signature_type.is_some() {
-            first_extent_position -= signature_type.unwrap().get_signature_length();
+        match compressed_file.check_content(unpacked_length, crc32_digest.finalize()) {
+            ContentVerdict::Ok => {}
+            ContentVerdict::SizeMismatch(expected, actual) => println!(
+                "Size mismatch, expected {} bytes, unpacked {}: {}",
+                expected, actual, path
+            ),
+            ContentVerdict::CrcMismatch(expected, actual) => println!(
+                "CRC mismatch, expected {:08x}, calculated {:08x}: {}",
+                expected, actual, path
+            ),
         }
-        if auth_type.is_some() {
-            first_extent_position -= auth_type.unwrap().get_hash_len() * (extents_count + 1);
+
+        match auth_verdict {
+            AuthVerdict::NotAuthenticated => {}
+            AuthVerdict::Ok => println!("Authentication ok: {}", path),
+            AuthVerdict::MismatchAtChunk(chunk_index) => println!(
+                "Authentication FAILED, hash mismatch at chunk {}: {}",
+                chunk_index, path
+            ),
         }
+        Ok(true)
+    }
 
-        let mut output_file = File::create(path)
-            .map_err(|e| VdfsError::FileWriteError(format!("Cannot create file: {}", e)))?;
-        for extent_index in 0..descriptor.extents_num {
-            let extent_position = first_extent_position + extent_size * extent_index as u64;
-            let extent: Vdfs4CompressedExtent =
-                temp_file_data_source.read_at(extent_position)?.data;
+    fn unpack_symlink(
+        &self,
+        path: &str,
+        file_object_id: u64,
+        catalog_file_record: &Vdfs4CatalogFileRecord,
+        as_file: bool,
+    ) -> Result<(), VdfsError> {
+        let target = self.read_link_target(file_object_id, catalog_file_record)?;
+        println!("Symbolic link: {} -> {}", path, target.display());
 
-            println!("Extent: {:?}", extent);
+        if as_file {
+            fs::write(path, target.as_os_str().as_bytes())
+                .map_err(|e| VdfsError::FileWriteError(format!("Cannot create file: {}", e)))
+        } else {
+            unix::fs::symlink(&target, path).map_err(|e| {
+                VdfsError::FileWriteError(format!("Cannot create symbolic link: {}", e))
+            })
+        }
+    }
 
-            if !extent.check_extent_signature() {
-                return Err(VdfsError::CompressedFileExtentWrongSignature);
-            }
-            if compressed_flag && extent.has_encrypted_flag() {
-                todo!("Encryption");
+    /// Creates device node, FIFO or socket, returns false if it was recorded in the manifest instead.
+    fn unpack_special_file(
+        &self,
+        context: &mut UnpackContext,
+        path: &str,
+        catalog_folder_record: &Vdfs4CatalogFolderRecord,
+    ) -> Result<bool, VdfsError> {
+        let file_type =
+            catalog_folder_record
+                .get_file_type()
+                .ok_or(VdfsError::UnsupportedFileMode(
+                    catalog_folder_record.file_mode,
+                ))?;
+        let (major, minor) = match file_type {
+            FileType::CharacterDevice | FileType::BlockDevice => {
+                catalog_folder_record.get_device_number()
             }
+            _ => (0, 0),
+        };
+        println!(
+            "Special file {:?}: {} ({}, {})",
+            file_type, path, major, minor
+        );
+
+        let c_path = CString::new(path)
+            .map_err(|e| VdfsError::FileWriteError(format!("Invalid path: {}", e)))?;
+        let result = unsafe {
+            libc::mknod(
+                c_path.as_ptr(),
+                catalog_folder_record.file_mode as libc::mode_t,
+                libc::makedev(major, minor),
+            )
+        };
+        if result == 0 {
+            return Ok(true);
+        }
 
-            let mut chunk_buffer =
-                temp_file_data_source.read_bytes_at(extent.start, extent.len_bytes as u64)?;
-            if extent.has_uncompressed_flag() {
-                output_file
-                    .write_all(chunk_buffer.as_mut_slice())
-                    .map_err(|e| {
-                        VdfsError::FileWriteError(format!("Cannot append to file: {}", e))
-                    })?;
-            } else {
-                match compression.as_ref().unwrap() {
-                    VdfsFileCompression::Zlib => {
-                        let mut decoder = zlib::Decoder::new(chunk_buffer.as_slice()).unwrap();
-                        let mut decoded_buffer = Vec::<u8>::new();
-                        decoder
-                            .read_to_end(&mut decoded_buffer)
-                            .map_err(|_| VdfsError::DecompressionError)?;
-                        output_file
-                            .write_all(decoded_buffer.as_mut_slice())
-                            .map_err(|e| {
-                                VdfsError::FileWriteError(format!("Cannot append to file: {}", e))
-                            })?;
-                    }
-                    VdfsFileCompression::Gzip => {
-                        let mut decoder = gzip::Decoder::new(chunk_buffer.as_slice()).unwrap();
-                        let mut decoded_buffer = Vec::<u8>::new();
-                        decoder
-                            .read_to_end(&mut decoded_buffer)
-                            .map_err(|_| VdfsError::DecompressionError)?;
-                        output_file
-                            .write_all(decoded_buffer.as_mut_slice())
-                            .map_err(|e| {
-                                VdfsError::FileWriteError(format!("Cannot append to file: {}", e))
-                            })?;
-                    }
-                    VdfsFileCompression::Lzo => todo!(),
-                }
-            }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::PermissionDenied {
+            return Err(VdfsError::FileWriteError(format!(
+                "Cannot create special file: {}",
+                error
+            )));
         }
+        let relative_path = context.get_relative_path(path);
+        context.special_files_manifest.push(format!(
+            "{} {} {} {} {:04o}",
+            relative_path,
+            file_type.get_type_char(),
+            major,
+            minor,
+            catalog_folder_record.file_mode & !FILE_TYPE_MASK
+        ));
+        Ok(false)
+    }
 
-        Ok(())
+    fn read_xattrs_to_restore(
+        &self,
+        context: &UnpackContext,
+        object_id: u64,
+    ) -> Result<Vec<(String, Vec<u8>)>, VdfsError> {
+        if context.options.skip_xattrs {
+            return Ok(Vec::new());
+        }
+        Ok(self.get_xattr_tree()?.entries(object_id)?)
     }
 
     fn unpack_raw_file(
@@ -278,95 +473,219 @@ impl<'a, S: DataSourceSource> Vdfs<'a, S> {
         file_object_id: u64,
         catalog_file_record: &Vdfs4CatalogFileRecord,
     ) -> Result<(), VdfsError> {
-        let mut file = fs::File::create(&path).unwrap();
+        let mut file = fs::File::create(path).unwrap();
         self.write_raw_data_to_file(&mut file, path, file_object_id, catalog_file_record)?;
         Ok(())
     }
 
-    fn create_temp_file_and_write_raw_data(
+    pub fn write_raw_data_to_file(
         &self,
+        file: &mut impl Write,
         path: &str,
         file_object_id: u64,
         catalog_file_record: &Vdfs4CatalogFileRecord,
-    ) -> Result<File, VdfsError> {
-        let mut temp_raw_file =
-            tempfile::tempfile().map_err(|e| VdfsError::FileWriteError(e.to_string()))?;
-        self.write_raw_data_to_file(
-            &mut temp_raw_file,
-            path,
-            file_object_id,
-            catalog_file_record,
-        )?;
-        Ok(temp_raw_file)
+    ) -> Result<(), VdfsError> {
+        let mut vdfs_file =
+            VdfsFile::new(self, file_object_id, Box::new(catalog_file_record.clone()));
+        // Read errors of the image come through io::Error as well
+        io::copy(&mut vdfs_file, file).map_err(|e| {
+            VdfsError::FileWriteError(format!("Cannot write to file {}: {}", path, e))
+        })?;
+        Ok(())
+    }
+}
+
+impl<'o> UnpackContext<'o> {
+    fn get_path(&self, record_key: &Vdfs4CatTreeKey) -> Result<String, VdfsError> {
+        let mut path = if record_key.parent_id == VDFS4_INVALID_NODE_ID {
+            self.root_path.clone()
+        } else {
+            self.folders_map
+                .get(&record_key.parent_id)
+                .ok_or(VdfsError::CannotFindParentFolder)?
+                .clone()
+        };
+        add_path_component(&mut path, &record_key.get_name_string());
+        Ok(path)
     }
 
-    fn write_raw_data_to_file(
+    /// Path inside the image without the leading slash, as written to the manifests.
+    fn get_relative_path<'p>(&self, path: &'p str) -> &'p str {
+        path.strip_prefix(&self.root_path)
+            .unwrap_or(path)
+            .trim_start_matches('/')
+    }
+
+    /// Applies owner, extended attributes, mode and timestamps without following symbolic links.
+    fn apply_metadata(
+        &mut self,
+        path: &str,
+        catalog_folder_record: &Vdfs4CatalogFolderRecord,
+        xattrs: &[(String, Vec<u8>)],
+    ) -> Result<(), VdfsError> {
+        if !self.options.skip_metadata {
+            self.apply_owner(path, catalog_folder_record)?;
+        }
+        // After the owner, because chown removes security.capability
+        self.restore_xattrs(path, xattrs)?;
+        if !self.options.skip_metadata {
+            self.apply_mode_and_times(path, catalog_folder_record)?;
+        }
+        Ok(())
+    }
+
+    fn apply_owner(
         &self,
-        file: &mut File,
         path: &str,
-        file_object_id: u64,
-        catalog_file_record: &Vdfs4CatalogFileRecord,
+        catalog_folder_record: &Vdfs4CatalogFolderRecord,
     ) -> Result<(), VdfsError> {
-        let mut bytes_left = catalog_file_record.data_fork.size_in_bytes;
-        let mut iblock = 0; // file logical block index
-
-        while bytes_left > 0 {
-            let iblock_position_in_blocks =
-                self.get_file_iblock_position(file_object_id, catalog_file_record, iblock)?;
-            let iblock_position_in_bytes = self.blocks_to_bytes(iblock_position_in_blocks);
-            let bytes_left_in_iblock = self.block_size.min(bytes_left);
-            let readed = self
-                .data_source
-                .read_bytes_at(iblock_position_in_bytes, bytes_left_in_iblock)?;
-            file.write_all(readed.as_slice()).map_err(|_| {
-                VdfsError::FileWriteError(format!("Cannot write to file: {}", path))
-            })?;
+        let uid = *self
+            .options
+            .uid_map
+            .get(&catalog_folder_record.uid)
+            .unwrap_or(&catalog_folder_record.uid);
+        let gid = *self
+            .options
+            .gid_map
+            .get(&catalog_folder_record.gid)
+            .unwrap_or(&catalog_folder_record.gid);
+        // Owner is changed first, because chown resets setuid and setgid bits
+        if let Err(e) = unix::fs::lchown(path, Some(uid), Some(gid)) {
+            if e.kind() != io::ErrorKind::PermissionDenied {
+                return Err(VdfsError::FileWriteError(format!(
+                    "Cannot change owner: {}",
+                    e
+                )));
+            }
+            println!("Cannot change owner of {} to {}:{}: {}", path, uid, gid, e);
+        }
+        Ok(())
+    }
 
-            bytes_left -= bytes_left_in_iblock;
-            iblock += 1;
+    fn restore_xattrs(
+        &mut self,
+        path: &str,
+        xattrs: &[(String, Vec<u8>)],
+    ) -> Result<(), VdfsError> {
+        let mut not_restored = Vec::new();
+        for (name, value) in xattrs {
+            let privileged = name.starts_with("security.") || name.starts_with("trusted.");
+            if privileged && !self.is_root && self.options.skip_privileged_xattrs {
+                not_restored.push((name, value));
+                continue;
+            }
+            if let Err(e) = set_xattr(path, name, value) {
+                println!("Cannot set extended attribute {} on {}: {}", name, path, e);
+                not_restored.push((name, value));
+            }
         }
 
+        if !not_restored.is_empty() {
+            let relative_path = self.get_relative_path(path);
+            self.xattrs_dump
+                .push_str(&format!("# file: {}\n", relative_path));
+            for (name, value) in not_restored {
+                self.xattrs_dump
+                    .push_str(&format!("{}={}\n", name, encode_xattr_value(value)));
+            }
+            self.xattrs_dump.push('\n');
+        }
         Ok(())
     }
 
-    fn get_file_iblock_position(
+    fn apply_mode_and_times(
         &self,
-        file_object_id: u64,
-        catalog_file_record: &Vdfs4CatalogFileRecord,
-        iblock: u64,
-    ) -> Result<u64, VdfsError> {
-        if iblock > catalog_file_record.data_fork.total_blocks_count {
-            panic!("File logical block is out of range");
-        }
-        for extent in &catalog_file_record.data_fork.extents {
-            if extent.iblock + extent.extent.length > iblock {
-                // this iblock is in the extent
-                let start_iblock_to_read = extent.extent.begin + iblock - extent.iblock;
-                return Ok(start_iblock_to_read);
-            }
+        path: &str,
+        catalog_folder_record: &Vdfs4CatalogFolderRecord,
+    ) -> Result<(), VdfsError> {
+        let is_symlink = fs::symlink_metadata(path)
+            .map_err(|e| VdfsError::FileWriteError(format!("Cannot read metadata: {}", e)))?
+            .is_symlink();
+
+        if !is_symlink {
+            let permissions = fs::Permissions::from_mode(
+                (catalog_folder_record.file_mode & !FILE_TYPE_MASK) as u32,
+            );
+            fs::set_permissions(path, permissions).map_err(|e| {
+                VdfsError::FileWriteError(format!("Cannot change permissions: {}", e))
+            })?;
         }
 
-        let extents_tree = self.extent_btree.as_ref().unwrap();
-        let extent_tree_iterator = extents_tree.records_iterator(file_object_id)?;
-
-        for extent_tree_record_key in extent_tree_iterator {
-            if extent_tree_record_key.data.object_id == file_object_id {
-                let extent_tree_record_value: Vdfs4ExtTreeRecord = extent_tree_record_key
-                    .get_record_value(self.data_source)?
-                    .data;
-                let last_iblock_in_record =
-                    extent_tree_record_value.key.iblock + extent_tree_record_value.lextent.length;
-                if last_iblock_in_record > iblock {
-                    let start_iblock_to_read = extent_tree_record_value.lextent.begin + iblock
-                        - extent_tree_record_value.key.iblock;
-                    return Ok(start_iblock_to_read);
-                }
-            } else {
-                break;
+        let c_path = CString::new(path)
+            .map_err(|e| VdfsError::FileWriteError(format!("Invalid path: {}", e)))?;
+        let times = [
+            to_libc_timespec(&catalog_folder_record.access_time),
+            to_libc_timespec(&catalog_folder_record.modification_time),
+        ];
+        let result = unsafe {
+            libc::utimensat(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if result != 0 {
+            return Err(VdfsError::FileWriteError(format!(
+                "Cannot change timestamps: {}",
+                io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn set_xattr(path: &str, name: &str, value: &[u8]) -> io::Result<()> {
+    let c_path = CString::new(path)?;
+    let c_name = CString::new(name)?;
+    let result = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Encodes the value as getfattr does: quoted text if it is printable, hex otherwise.
+fn encode_xattr_value(value: &[u8]) -> String {
+    let text = value.strip_suffix(&[0]).unwrap_or(value);
+    if !text.is_empty() && text.iter().all(|byte| (0x20..0x7f).contains(byte)) {
+        let mut encoded = String::from("\"");
+        for byte in value {
+            match byte {
+                b'"' | b'\\' | 0 => encoded.push_str(&format!("\\{:03o}", byte)),
+                _ => encoded.push(*byte as char),
             }
         }
+        encoded.push('"');
+        encoded
+    } else {
+        let mut encoded = String::from("0x");
+        for byte in value {
+            encoded.push_str(&format!("{:02x}", byte));
+        }
+        encoded
+    }
+}
 
-        Err(VdfsError::FileBlockNotFound(iblock))
+/// Times that do not fit into timespec are left unchanged.
+fn to_libc_timespec(timespec: &Vdfs4Timespec) -> libc::timespec {
+    match libc::time_t::try_from(timespec.get_seconds()) {
+        Ok(seconds) if timespec.nanoseconds < 1_000_000_000 => libc::timespec {
+            tv_sec: seconds,
+            tv_nsec: timespec.nanoseconds as libc::c_long,
+        },
+        _ => libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
     }
 }
 
@@ -374,3 +693,199 @@ fn add_path_component(path: &mut String, component: &str) {
     path.push('/');
     path.push_str(component);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdfs::test_image::*;
+    use std::{os::unix::fs::MetadataExt, path::PathBuf};
+
+    /// Unpacks into a new folder in the temporary folder, returns the output folder,
+    /// the content of the image is in its "root" subfolder.
+    fn unpack_image(data_source: &TestDataSource, name: &str, options: &UnpackOptions) -> String {
+        let output_path = std::env::temp_dir()
+            .join(format!("vdfs_utils_{}_{}", std::process::id(), name))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = fs::remove_dir_all(&output_path);
+        let vdfs = Vdfs::open(data_source).unwrap();
+        vdfs.unpack_with_options(&output_path, options).unwrap();
+        output_path
+    }
+
+    fn symlinks_image() -> TestDataSource {
+        let mut image = TestImage::new();
+        image
+            .add_folder(1, "usr", 10, folder_record())
+            .add_folder(10, "lib", 11, folder_record())
+            .add_regular_file(11, "libc.so", 20, b"libc")
+            .add_symlink(1, "lib", 21, "usr/lib")
+            .add_symlink(1, "dangling", 22, "/nonexistent");
+        image.build()
+    }
+
+    #[test]
+    fn unpack_symlinks() {
+        let data_source = symlinks_image();
+        let output_path = unpack_image(&data_source, "symlinks", &UnpackOptions::default());
+
+        let link_path = format!("{}/root/lib", output_path);
+        assert!(fs::symlink_metadata(&link_path).unwrap().is_symlink());
+        assert_eq!(fs::read_link(&link_path).unwrap(), PathBuf::from("usr/lib"));
+        assert_eq!(fs::read(format!("{}/libc.so", link_path)).unwrap(), b"libc");
+        // Metadata is applied to the link itself, the missing target is not followed
+        let dangling_path = format!("{}/root/dangling", output_path);
+        assert_eq!(
+            fs::read_link(&dangling_path).unwrap(),
+            PathBuf::from("/nonexistent")
+        );
+        fs::remove_dir_all(output_path).unwrap();
+    }
+
+    #[test]
+    fn unpack_symlinks_as_files() {
+        let data_source = symlinks_image();
+        let options = UnpackOptions {
+            symlinks_as_files: true,
+            ..Default::default()
+        };
+        let output_path = unpack_image(&data_source, "symlinks_as_files", &options);
+
+        for (name, target) in [("lib", "usr/lib"), ("dangling", "/nonexistent")] {
+            let path = format!("{}/root/{}", output_path, name);
+            assert!(fs::symlink_metadata(&path).unwrap().is_file());
+            assert_eq!(fs::read_to_string(&path).unwrap(), target);
+        }
+        fs::remove_dir_all(output_path).unwrap();
+    }
+
+    #[test]
+    fn unpack_hard_links() {
+        let mut image = TestImage::new();
+        let mut record = file_record(FileType::Regular, 6);
+        record.common.links_count = 3;
+        record.data_fork.extents[0] = image.add_data(0, b"shared");
+        image
+            .add_folder(1, "bin", 10, folder_record())
+            .add_file(20, "", 20, record)
+            .add_hard_link(1, "first", 20, FileType::Regular)
+            .add_hard_link(1, "second", 20, FileType::Regular)
+            .add_hard_link(10, "third", 20, FileType::Regular)
+            .add_regular_file(1, "single", 21, b"single");
+        let data_source = image.build();
+        let output_path = unpack_image(&data_source, "hard_links", &UnpackOptions::default());
+
+        let first = fs::metadata(format!("{}/root/first", output_path)).unwrap();
+        for name in ["second", "bin/third"] {
+            let path = format!("{}/root/{}", output_path, name);
+            assert_eq!(fs::read(&path).unwrap(), b"shared");
+            assert_eq!(fs::metadata(&path).unwrap().ino(), first.ino());
+        }
+        assert_eq!(first.nlink(), 3);
+        assert_eq!(
+            fs::metadata(format!("{}/root/single", output_path))
+                .unwrap()
+                .nlink(),
+            1
+        );
+        // The nameless inode record is not unpacked by itself
+        assert_eq!(
+            fs::read_dir(format!("{}/root", output_path))
+                .unwrap()
+                .count(),
+            4
+        );
+        fs::remove_dir_all(output_path).unwrap();
+    }
+
+    fn timespec(seconds: u32, nanoseconds: u32) -> Vdfs4Timespec {
+        Vdfs4Timespec {
+            seconds,
+            seconds_high: 0,
+            nanoseconds,
+        }
+    }
+
+    /// Read-only folder with a file that has its own mode, owner and timestamps.
+    fn metadata_image() -> TestDataSource {
+        let mut image = TestImage::new();
+        let mut folder = folder_record();
+        folder.file_mode = FileType::Directory.to_u16() | 0o500;
+        folder.modification_time = timespec(1_500_000_000, 0);
+        let mut file = file_record(FileType::Regular, 4);
+        file.common.file_mode = FileType::Regular.to_u16() | 0o640;
+        file.common.uid = 1000;
+        file.common.gid = 1001;
+        file.common.modification_time = timespec(1_000_000_000, 5);
+        file.common.access_time = timespec(1_100_000_000, 0);
+        file.data_fork.extents[0] = image.add_data(0, b"data");
+        image
+            .add_folder(1, "etc", 10, folder)
+            .add_file(10, "passwd", 20, file);
+        image.build()
+    }
+
+    #[test]
+    fn apply_metadata_from_image() {
+        let data_source = metadata_image();
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let options = UnpackOptions {
+            uid_map: BTreeMap::from([(1000, uid)]),
+            gid_map: BTreeMap::from([(1001, gid)]),
+            ..Default::default()
+        };
+        let output_path = unpack_image(&data_source, "metadata", &options);
+
+        let file = fs::metadata(format!("{}/root/etc/passwd", output_path)).unwrap();
+        assert_eq!(file.mode() & 0o7777, 0o640);
+        assert_eq!((file.uid(), file.gid()), (uid, gid));
+        assert_eq!((file.mtime(), file.mtime_nsec()), (1_000_000_000, 5));
+        assert_eq!(file.atime(), 1_100_000_000);
+        // Applied after the file was written into it
+        let folder_path = format!("{}/root/etc", output_path);
+        let folder = fs::metadata(&folder_path).unwrap();
+        assert_eq!(folder.mode() & 0o7777, 0o500);
+        assert_eq!(folder.mtime(), 1_500_000_000);
+
+        fs::set_permissions(&folder_path, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::remove_dir_all(output_path).unwrap();
+    }
+
+    #[test]
+    fn skip_metadata() {
+        let data_source = metadata_image();
+        let options = UnpackOptions {
+            skip_metadata: true,
+            ..Default::default()
+        };
+        let output_path = unpack_image(&data_source, "skip_metadata", &options);
+
+        let file = fs::metadata(format!("{}/root/etc/passwd", output_path)).unwrap();
+        assert_ne!(file.mtime(), 1_000_000_000);
+        assert_ne!(file.uid(), 1000);
+        let folder = fs::metadata(format!("{}/root/etc", output_path)).unwrap();
+        assert_ne!(folder.mode() & 0o7777, 0o500);
+        fs::remove_dir_all(output_path).unwrap();
+    }
+
+    #[test]
+    fn text_xattr_value() {
+        assert_eq!(encode_xattr_value(b"user value"), "\"user value\"");
+        assert_eq!(encode_xattr_value(b"a\"b\\c"), "\"a\\042b\\134c\"");
+    }
+
+    #[test]
+    fn nul_terminated_xattr_value() {
+        assert_eq!(encode_xattr_value(b"text\0"), "\"text\\000\"");
+        // A lone NUL is not text
+        assert_eq!(encode_xattr_value(b"\0"), "0x00");
+    }
+
+    #[test]
+    fn binary_xattr_value() {
+        assert_eq!(encode_xattr_value(&[0x01, 0x00, 0xff]), "0x0100ff");
+        assert_eq!(encode_xattr_value(b"te\0xt"), "0x7465007874");
+        assert_eq!(encode_xattr_value(b"line\n"), "0x6c696e650a");
+    }
+}
@@ -1,4 +1,4 @@
-use crc::{Algorithm, Crc};
+use crc::{Algorithm, Crc, Digest};
 
 const CRC_32_VDFS: Algorithm<u32> = Algorithm {
     width: 32,
@@ -11,7 +11,13 @@ const CRC_32_VDFS: Algorithm<u32> = Algorithm {
     residue: 0x00000000,
 };
 
+static CRC_VDFS: Crc<u32> = Crc::<u32>::new(&CRC_32_VDFS);
+
 pub fn crc32(bytes: &[u8]) -> u32 {
-    let crc = Crc::<u32>::new(&CRC_32_VDFS);
-    crc.checksum(bytes)
+    CRC_VDFS.checksum(bytes)
+}
+
+/// CRC32 of data that comes in parts.
+pub fn crc32_digest() -> Digest<'static, u32> {
+    CRC_VDFS.digest()
 }